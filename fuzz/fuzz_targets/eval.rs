@@ -0,0 +1,15 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use std::io;
+
+fuzz_target!(|source: &str| {
+    for backend in [rlox::Backend::TreeWalk, rlox::Backend::Vm] {
+        let mut lox = rlox::Lox::with_backend(backend);
+        lox.interpreter.set_output(Box::new(io::sink()));
+        lox.vm.set_output(Box::new(io::sink()));
+        // Loops can legitimately run forever
+        lox.interpreter.set_step_limit(Some(100_000));
+        lox.vm.set_step_limit(Some(100_000));
+        let _ = lox.eval(source);
+    }
+});
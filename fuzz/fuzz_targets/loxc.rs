@@ -0,0 +1,14 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use std::io;
+
+fuzz_target!(|bytes: &[u8]| {
+    if let Ok(script) = rlox::loxc::decode(bytes) {
+        let _ = rlox::disassemble(&script);
+        // Decoded scripts may loop forever, so cap how long they run
+        let mut vm = rlox::Vm::new();
+        vm.set_output(Box::new(io::sink()));
+        vm.set_step_limit(Some(100_000));
+        let _ = vm.interpret(script);
+    }
+});
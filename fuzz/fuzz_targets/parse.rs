@@ -0,0 +1,8 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|source: &str| {
+    if let Ok(tokens) = rlox::Scanner::scan_tokens(source.to_string()) {
+        let _ = rlox::Parser::parse_partial(tokens);
+    }
+});
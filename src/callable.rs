@@ -0,0 +1,71 @@
+use crate::class::LoxInstance;
+use crate::environment::Environment;
+use crate::error::LoxError;
+use crate::gc::{self, Trace};
+use crate::interpreter::{Interpreter, Unwind};
+use crate::stmt;
+use crate::value::Value;
+use std::{cell::RefCell, rc::Rc};
+
+pub trait Callable {
+    fn arity(&self) -> usize;
+    fn call(&self, interpreter: &mut Interpreter, arguments: Vec<Value>)
+        -> Result<Value, LoxError>;
+}
+
+pub struct LoxFunction {
+    pub declaration: Rc<stmt::Function>,
+    pub closure: Option<Rc<RefCell<Environment>>>, // local scope active when the function was declared
+    pub is_initializer: bool,
+}
+impl LoxFunction {
+    /// Returns a copy of this method whose closure defines `this` as `instance`.
+    pub fn bind(&self, instance: Rc<RefCell<LoxInstance>>) -> LoxFunction {
+        let mut environment = Environment::new(self.closure.clone());
+        environment.define(Value::Instance(instance));
+        LoxFunction {
+            declaration: self.declaration.clone(),
+            closure: Some(gc::alloc(RefCell::new(environment))),
+            is_initializer: self.is_initializer,
+        }
+    }
+
+    /// The instance a bound method was bound to.
+    fn this(&self) -> Value {
+        self.closure
+            .as_ref()
+            .and_then(|closure| closure.borrow().get_at(0, 0))
+            .unwrap_or(Value::Nil)
+    }
+}
+impl Callable for LoxFunction {
+    fn arity(&self) -> usize {
+        self.declaration.params.len()
+    }
+
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, LoxError> {
+        let mut environment = Environment::new(self.closure.clone());
+        arguments
+            .into_iter()
+            .for_each(|argument| environment.define(argument));
+        match interpreter
+            .execute_block(&self.declaration.body, gc::alloc(RefCell::new(environment)))
+        {
+            Ok(_) | Err(Unwind::Return(_)) if self.is_initializer => Ok(self.this()),
+            Ok(_) => Ok(Value::Nil),
+            Err(Unwind::Return(value)) => Ok(value),
+            Err(unwind) => Err(unwind.into_error()),
+        }
+    }
+}
+impl Trace for LoxFunction {
+    fn trace(&self, visit: &mut dyn FnMut(usize)) {
+        if let Some(closure) = &self.closure {
+            visit(gc::id(closure));
+        }
+    }
+}
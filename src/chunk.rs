@@ -0,0 +1,171 @@
+use crate::error::Span;
+use crate::symbol::Symbol;
+use std::{fmt, rc::Rc};
+
+/// One VM instruction. Operands follow the opcode in the chunk; multi-byte
+/// operands are big-endian.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum OpCode {
+    Constant, // u16 constant index
+    Nil,
+    True,
+    False,
+    Pop,
+    GetLocal,     // u8 stack slot, relative to the frame
+    SetLocal,     // u8 stack slot
+    GetGlobal,    // u16 name constant
+    DefineGlobal, // u16 name constant
+    SetGlobal,    // u16 name constant
+    GetUpvalue,   // u8 upvalue index
+    SetUpvalue,   // u8 upvalue index
+    GetProperty,  // u16 name constant
+    SetProperty,  // u16 name constant
+    GetSuper,     // u16 name constant
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Not,
+    Negate,
+    Print,
+    Jump,        // u16 forward offset
+    JumpIfFalse, // u16 forward offset, leaves the condition on the stack
+    Loop,        // u16 backward offset
+    Call,        // u8 argument count
+    Closure,     // u16 function constant, then u8 is_local and u8 index per upvalue
+    CloseUpvalue,
+    Return,
+    Class, // u16 name constant, u8 has superclass, u16 method count, u16 name constant per method
+    List,  // u16 element count
+    Map,   // pushes an empty map
+    MapEntry, // pops a key and a value into the map below them
+    GetIndex,
+    SetIndex,
+}
+impl OpCode {
+    /// Every opcode, indexed by its byte.
+    const ALL: [OpCode; 41] = [
+        OpCode::Constant,
+        OpCode::Nil,
+        OpCode::True,
+        OpCode::False,
+        OpCode::Pop,
+        OpCode::GetLocal,
+        OpCode::SetLocal,
+        OpCode::GetGlobal,
+        OpCode::DefineGlobal,
+        OpCode::SetGlobal,
+        OpCode::GetUpvalue,
+        OpCode::SetUpvalue,
+        OpCode::GetProperty,
+        OpCode::SetProperty,
+        OpCode::GetSuper,
+        OpCode::Equal,
+        OpCode::NotEqual,
+        OpCode::Greater,
+        OpCode::GreaterEqual,
+        OpCode::Less,
+        OpCode::LessEqual,
+        OpCode::Add,
+        OpCode::Subtract,
+        OpCode::Multiply,
+        OpCode::Divide,
+        OpCode::Not,
+        OpCode::Negate,
+        OpCode::Print,
+        OpCode::Jump,
+        OpCode::JumpIfFalse,
+        OpCode::Loop,
+        OpCode::Call,
+        OpCode::Closure,
+        OpCode::CloseUpvalue,
+        OpCode::Return,
+        OpCode::Class,
+        OpCode::List,
+        OpCode::Map,
+        OpCode::MapEntry,
+        OpCode::GetIndex,
+        OpCode::SetIndex,
+    ];
+}
+impl TryFrom<u8> for OpCode {
+    type Error = u8;
+
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        OpCode::ALL.get(byte as usize).copied().ok_or(byte)
+    }
+}
+
+/// A constant referenced by index from a chunk's code.
+#[derive(Debug, Clone)]
+pub enum Constant {
+    Number(f64),
+    String(Symbol),
+    Function(Rc<FunctionProto>),
+}
+
+/// Compiled code plus the constants it uses. `spans` has one entry per byte
+/// of `code`, so runtime errors can point back at the source.
+#[derive(Debug, Clone, Default)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    pub constants: Vec<Constant>,
+    pub spans: Vec<Span>,
+}
+impl Chunk {
+    pub fn write(&mut self, byte: u8, span: Span) {
+        self.code.push(byte);
+        self.spans.push(span);
+    }
+
+    pub fn write_op(&mut self, op: OpCode, span: Span) {
+        self.write(op as u8, span);
+    }
+
+    pub fn write_u16(&mut self, value: u16, span: Span) {
+        for byte in value.to_be_bytes() {
+            self.write(byte, span);
+        }
+    }
+
+    pub fn read_u16(&self, offset: usize) -> u16 {
+        u16::from_be_bytes([self.code[offset], self.code[offset + 1]])
+    }
+
+    /// Overwrites the u16 operand at `offset`, e.g. to patch a jump.
+    pub fn patch_u16(&mut self, offset: usize, value: u16) {
+        self.code[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+    }
+
+    /// Adds a constant and returns its index, or None if the chunk is full.
+    pub fn add_constant(&mut self, constant: Constant) -> Option<u16> {
+        let index = u16::try_from(self.constants.len()).ok()?;
+        self.constants.push(constant);
+        Some(index)
+    }
+}
+
+/// A compiled function before it closes over any variables. The top-level
+/// script is compiled to one too, with an empty name.
+#[derive(Debug, Clone, Default)]
+pub struct FunctionProto {
+    pub name: String,
+    pub arity: usize,
+    pub upvalue_count: usize,
+    pub chunk: Chunk,
+}
+impl fmt::Display for FunctionProto {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.name.as_str() {
+            "" => write!(f, "<script>"),
+            name => write!(f, "<fn {}>", name),
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use crate::callable::{Callable, LoxFunction};
+use crate::error::Span;
+use crate::error::{ErrorCode, LoxError};
+use crate::gc::{self, Trace};
+use crate::interpreter::Interpreter;
+use crate::symbol::Symbol;
+use crate::value::{self, Value};
+use crate::vm::{BoundMethod, Closure};
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+/// A method body: a declaration run by the tree-walker, or a closure
+/// compiled for the VM. A class only ever holds one kind.
+#[derive(Clone)]
+pub enum Method {
+    Function(Rc<LoxFunction>),
+    Closure(Rc<Closure>),
+}
+impl Method {
+    pub fn arity(&self) -> usize {
+        match self {
+            Method::Function(function) => function.arity(),
+            Method::Closure(closure) => closure.function.arity,
+        }
+    }
+
+    /// Returns the method as a callable value with `this` bound to `instance`.
+    pub fn bind(&self, instance: Rc<RefCell<LoxInstance>>) -> Value {
+        match self {
+            Method::Function(function) => Value::Function(gc::alloc(function.bind(instance))),
+            Method::Closure(closure) => Value::BoundMethod(gc::alloc(BoundMethod {
+                receiver: instance,
+                method: closure.clone(),
+            })),
+        }
+    }
+}
+
+pub struct LoxClass {
+    pub name: Symbol,
+    pub superclass: Option<Rc<LoxClass>>,
+    pub methods: HashMap<Symbol, Method>,
+}
+impl LoxClass {
+    pub fn find_method(&self, name: &Symbol) -> Option<Method> {
+        match self.methods.get(name) {
+            Some(method) => Some(method.clone()),
+            None => self
+                .superclass
+                .as_ref()
+                .and_then(|superclass| superclass.find_method(name)),
+        }
+    }
+}
+impl Trace for LoxClass {
+    fn trace(&self, visit: &mut dyn FnMut(usize)) {
+        if let Some(superclass) = &self.superclass {
+            visit(gc::id(superclass));
+        }
+        for method in self.methods.values() {
+            match method {
+                Method::Function(function) => visit(gc::id(function)),
+                Method::Closure(closure) => visit(gc::id(closure)),
+            }
+        }
+    }
+}
+impl Drop for LoxClass {
+    fn drop(&mut self) {
+        value::release(self.superclass.take().map(Value::Class));
+    }
+}
+impl Callable for Rc<LoxClass> {
+    fn arity(&self) -> usize {
+        match self.find_method(&Symbol::intern("init")) {
+            Some(initializer) => initializer.arity(),
+            None => 0,
+        }
+    }
+
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, LoxError> {
+        let instance = gc::alloc(RefCell::new(LoxInstance {
+            class: self.clone(),
+            fields: HashMap::new(),
+        }));
+        if let Some(Method::Function(initializer)) = self.find_method(&Symbol::intern("init")) {
+            initializer
+                .bind(instance.clone())
+                .call(interpreter, arguments)?;
+        }
+        Ok(Value::Instance(instance))
+    }
+}
+
+pub struct LoxInstance {
+    pub class: Rc<LoxClass>,
+    pub fields: HashMap<Symbol, Value>,
+}
+impl LoxInstance {
+    /// Looks up a property, preferring fields over methods. Methods are bound
+    /// to `instance` so `this` refers to it when they are called later.
+    /// A missing property is reported at `span`.
+    pub fn get(
+        instance: &Rc<RefCell<LoxInstance>>,
+        name: &Symbol,
+        span: Span,
+    ) -> Result<Value, LoxError> {
+        if let Some(value) = instance.borrow().fields.get(name) {
+            return Ok(value.clone());
+        }
+        let method = instance.borrow().class.find_method(name);
+        match method {
+            Some(method) => Ok(method.bind(instance.clone())),
+            None => Err(LoxError::runtime(
+                ErrorCode::UndefinedProperty,
+                span,
+                format!("Undefined property '{}'", name),
+            )),
+        }
+    }
+
+    pub fn set(&mut self, name: &Symbol, value: Value) {
+        self.fields.insert(name.clone(), value);
+    }
+}
+impl Trace for RefCell<LoxInstance> {
+    fn trace(&self, visit: &mut dyn FnMut(usize)) {
+        if let Ok(instance) = self.try_borrow() {
+            visit(gc::id(&instance.class));
+            instance
+                .fields
+                .values()
+                .for_each(|value| value.trace(visit));
+        }
+    }
+
+    fn clear(&self) {
+        if let Ok(mut instance) = self.try_borrow_mut() {
+            instance.fields.clear();
+        }
+    }
+}
+impl Drop for LoxInstance {
+    fn drop(&mut self) {
+        value::release(std::mem::take(&mut self.fields).into_values());
+    }
+}
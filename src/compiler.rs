@@ -0,0 +1,713 @@
+use crate::chunk::{Chunk, Constant, FunctionProto, OpCode};
+use crate::error::{ErrorCode, LoxError, Span};
+use crate::expr::{self, Literal};
+use crate::stmt;
+use crate::symbol::Symbol;
+use crate::token_type::TokenType;
+use std::rc::Rc;
+
+/// Locals and upvalues are addressed with one byte.
+const MAX_SLOTS: usize = 256;
+
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionKind {
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
+struct Local {
+    name: String,
+    depth: usize,
+    captured: bool, // closed over by a nested function, so it must be hoisted on scope exit
+}
+
+/// Where a closure finds a captured variable when it is created: a local
+/// slot of the enclosing function, or one of the enclosing function's own
+/// upvalues.
+#[derive(Clone, Copy, PartialEq)]
+struct UpvalueRef {
+    index: u8,
+    is_local: bool,
+}
+
+struct Loop {
+    scope_depth: usize,
+    breaks: Vec<usize>,    // jump operands to patch to the loop exit
+    continues: Vec<usize>, // jump operands to patch to the increment
+}
+
+/// State for one function being compiled. Nested function declarations push
+/// a new one.
+struct FunctionState {
+    proto: FunctionProto,
+    kind: FunctionKind,
+    locals: Vec<Local>,
+    upvalues: Vec<UpvalueRef>,
+    scope_depth: usize,
+    loops: Vec<Loop>,
+}
+impl FunctionState {
+    fn new(name: String, kind: FunctionKind) -> Self {
+        // Slot 0 holds the callee, or the receiver in methods
+        let receiver = match kind {
+            FunctionKind::Method | FunctionKind::Initializer => "this",
+            _ => "",
+        };
+        FunctionState {
+            proto: FunctionProto {
+                name,
+                ..Default::default()
+            },
+            kind,
+            locals: vec![Local {
+                name: receiver.to_string(),
+                depth: 0,
+                captured: false,
+            }],
+            upvalues: vec![],
+            scope_depth: match kind {
+                FunctionKind::Script => 0,
+                _ => 1,
+            },
+            loops: vec![],
+        }
+    }
+}
+
+enum Access {
+    Local(u8),
+    Upvalue(u8),
+    Global(u16),
+}
+
+/// Compiles a resolved AST to bytecode for the VM. The resolver has already
+/// reported misplaced `return`, `this`, `break` and the like, so the only
+/// errors left are the VM's limits on chunk size.
+pub struct Compiler {
+    functions: Vec<FunctionState>, // innermost last
+    errors: Vec<LoxError>,
+}
+impl Compiler {
+    /// Compiles a script. Like `Interpreter::interpret_stmts`, the compiled
+    /// script returns the value of a trailing expression statement.
+    pub fn compile(stmts: &[stmt::Stmt]) -> Result<Rc<FunctionProto>, Vec<LoxError>> {
+        let mut compiler = Compiler {
+            functions: vec![FunctionState::new(String::new(), FunctionKind::Script)],
+            errors: vec![],
+        };
+        match stmts.split_last() {
+            Some((stmt::Stmt::Expr(last), init)) => {
+                compiler.stmts(init);
+                compiler.expr(&last.expression);
+                compiler.emit(OpCode::Return, last.span);
+            }
+            Some((last, _)) => {
+                compiler.stmts(stmts);
+                compiler.emit(OpCode::Nil, last.span());
+                compiler.emit(OpCode::Return, last.span());
+            }
+            None => {
+                compiler.emit(OpCode::Nil, Span::default());
+                compiler.emit(OpCode::Return, Span::default());
+            }
+        }
+        if !compiler.errors.is_empty() {
+            return Err(compiler.errors);
+        }
+        let script = compiler.functions.pop().map(|state| state.proto);
+        Ok(Rc::new(script.unwrap_or_default()))
+    }
+
+    fn stmts(&mut self, stmts: &[stmt::Stmt]) {
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &stmt::Stmt) {
+        match stmt {
+            stmt::Stmt::Expr(expr_stmt) => {
+                self.expr(&expr_stmt.expression);
+                self.emit(OpCode::Pop, expr_stmt.span);
+            }
+            stmt::Stmt::Print(print_stmt) => {
+                self.expr(&print_stmt.expression);
+                self.emit(OpCode::Print, print_stmt.span);
+            }
+            stmt::Stmt::VarDec(var_stmt) => {
+                match &var_stmt.expression {
+                    Some(expression) => self.expr(expression),
+                    None => self.emit(OpCode::Nil, var_stmt.span),
+                }
+                self.define_variable(&var_stmt.name.lexeme, var_stmt.name.span);
+            }
+            stmt::Stmt::Block(block_stmt) => {
+                self.begin_scope();
+                self.stmts(&block_stmt.statements);
+                self.end_scope(block_stmt.span);
+            }
+            stmt::Stmt::If(if_stmt) => {
+                let (ifs, else_branch) = if_stmt.ladder();
+                let mut end_jumps = vec![];
+                for if_stmt in ifs {
+                    let span = if_stmt.condition.span();
+                    self.expr(&if_stmt.condition);
+                    let then_jump = self.emit_jump(OpCode::JumpIfFalse, span);
+                    self.emit(OpCode::Pop, span);
+                    self.stmt(&if_stmt.then_branch);
+                    end_jumps.push((self.emit_jump(OpCode::Jump, span), span));
+                    self.patch_jump(then_jump, span);
+                    self.emit(OpCode::Pop, span);
+                }
+                if let Some(else_branch) = else_branch {
+                    self.stmt(else_branch);
+                }
+                for (jump, span) in end_jumps.into_iter().rev() {
+                    self.patch_jump(jump, span);
+                }
+            }
+            stmt::Stmt::While(while_stmt) => self.while_stmt(while_stmt),
+            stmt::Stmt::Function(function_stmt) => {
+                let name = &function_stmt.name;
+                // Declared before the body is compiled so the function can recurse
+                if self.state().scope_depth > 0 {
+                    self.add_local(&name.lexeme, name.span);
+                    self.function(function_stmt, FunctionKind::Function);
+                } else {
+                    self.function(function_stmt, FunctionKind::Function);
+                    self.define_variable(&name.lexeme, name.span);
+                }
+            }
+            stmt::Stmt::Return(return_stmt) => {
+                let span = return_stmt.keyword.span;
+                match &return_stmt.value {
+                    Some(value) => self.expr(value),
+                    None => self.emit_implicit_return_value(span),
+                }
+                self.emit(OpCode::Return, span);
+            }
+            stmt::Stmt::Class(class_stmt) => self.class(class_stmt),
+            stmt::Stmt::Break(break_stmt) => {
+                self.exit_loop_scopes(break_stmt.span);
+                let jump = self.emit_jump(OpCode::Jump, break_stmt.span);
+                if let Some(innermost) = self.state().loops.last_mut() {
+                    innermost.breaks.push(jump);
+                }
+            }
+            stmt::Stmt::Continue(continue_stmt) => {
+                self.exit_loop_scopes(continue_stmt.span);
+                let jump = self.emit_jump(OpCode::Jump, continue_stmt.span);
+                if let Some(innermost) = self.state().loops.last_mut() {
+                    innermost.continues.push(jump);
+                }
+            }
+        }
+    }
+
+    fn while_stmt(&mut self, stmt: &stmt::While) {
+        let span = stmt.condition.span();
+        let start = self.chunk().code.len();
+        self.expr(&stmt.condition);
+        let exit_jump = self.emit_jump(OpCode::JumpIfFalse, span);
+        self.emit(OpCode::Pop, span);
+
+        let scope_depth = self.state().scope_depth;
+        self.state().loops.push(Loop {
+            scope_depth,
+            breaks: vec![],
+            continues: vec![],
+        });
+        self.stmt(&stmt.body);
+        let innermost = self.state().loops.pop();
+        let (breaks, continues) = innermost
+            .map(|innermost| (innermost.breaks, innermost.continues))
+            .unwrap_or_default();
+
+        for jump in continues {
+            self.patch_jump(jump, span);
+        }
+        if let Some(increment) = &stmt.increment {
+            self.expr(increment);
+            self.emit(OpCode::Pop, increment.span());
+        }
+        self.emit_loop(start, span);
+        self.patch_jump(exit_jump, span);
+        self.emit(OpCode::Pop, span);
+        // A break skips the Pop above: the condition was already popped
+        for jump in breaks {
+            self.patch_jump(jump, span);
+        }
+    }
+
+    /// Discards the locals declared inside the innermost loop, ahead of a
+    /// jump out of its body. The compiler keeps tracking them, since code
+    /// after the jump is still in their scope.
+    fn exit_loop_scopes(&mut self, span: Span) {
+        let state = self.state();
+        let Some(depth) = state.loops.last().map(|innermost| innermost.scope_depth) else {
+            return;
+        };
+        let ops: Vec<OpCode> = state
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth > depth)
+            .map(|local| match local.captured {
+                true => OpCode::CloseUpvalue,
+                false => OpCode::Pop,
+            })
+            .collect();
+        for op in ops {
+            self.emit(op, span);
+        }
+    }
+
+    fn function(&mut self, function: &stmt::Function, kind: FunctionKind) {
+        let span = function.name.span;
+        self.functions
+            .push(FunctionState::new(function.name.lexeme.to_string(), kind));
+        self.state().proto.arity = function.params.len();
+        for param in &function.params {
+            self.add_local(&param.lexeme, param.span);
+        }
+        self.stmts(&function.body);
+        self.emit_implicit_return_value(function.span);
+        self.emit(OpCode::Return, function.span);
+
+        let Some(state) = self.functions.pop() else {
+            return;
+        };
+        let mut proto = state.proto;
+        proto.upvalue_count = state.upvalues.len();
+        let constant = self.make_constant(Constant::Function(Rc::new(proto)), span);
+        self.emit(OpCode::Closure, span);
+        self.chunk().write_u16(constant, span);
+        for upvalue in state.upvalues {
+            self.chunk().write(upvalue.is_local as u8, span);
+            self.chunk().write(upvalue.index, span);
+        }
+    }
+
+    /// Pushes what a function returns when it has no `return` value:
+    /// initializers return the instance, everything else nil.
+    fn emit_implicit_return_value(&mut self, span: Span) {
+        match self.state().kind {
+            FunctionKind::Initializer => {
+                self.emit(OpCode::GetLocal, span);
+                self.chunk().write(0, span);
+            }
+            _ => self.emit(OpCode::Nil, span),
+        }
+    }
+
+    fn class(&mut self, class: &stmt::Class) {
+        let name = self.identifier(&class.name.lexeme, class.name.span);
+        // Reserve the class's slot first so methods can capture it
+        let slot = match self.state().scope_depth {
+            0 => None,
+            _ => {
+                self.emit(OpCode::Nil, class.name.span);
+                self.add_local(&class.name.lexeme, class.name.span);
+                Some(self.state().locals.len() - 1)
+            }
+        };
+        // The superclass stays on the stack while the methods are created, as
+        // a local named 'super' that they close over
+        if let Some(superclass) = &class.superclass {
+            self.get_variable(&superclass.name.lexeme, superclass.name.span);
+            self.begin_scope();
+            self.add_local("super", superclass.name.span);
+        }
+        let mut methods = vec![];
+        for method in &class.methods {
+            let kind = match method.name.lexeme.as_str() {
+                "init" => FunctionKind::Initializer,
+                _ => FunctionKind::Method,
+            };
+            self.function(method, kind);
+            methods.push(self.identifier(&method.name.lexeme, method.name.span));
+        }
+
+        // Errors from this instruction are about the superclass
+        let span = match &class.superclass {
+            Some(superclass) => superclass.name.span,
+            None => class.name.span,
+        };
+        self.emit(OpCode::Class, span);
+        self.chunk().write_u16(name, span);
+        self.chunk().write(class.superclass.is_some() as u8, span);
+        self.chunk().write_u16(methods.len() as u16, span);
+        for method in methods {
+            self.chunk().write_u16(method, span);
+        }
+
+        match slot {
+            Some(slot) => {
+                self.emit(OpCode::SetLocal, class.name.span);
+                self.chunk().write(slot as u8, class.name.span);
+                self.emit(OpCode::Pop, class.name.span);
+            }
+            None => {
+                self.emit(OpCode::DefineGlobal, class.name.span);
+                self.chunk().write_u16(name, class.name.span);
+            }
+        }
+        if class.superclass.is_some() {
+            self.end_scope(class.span);
+        }
+    }
+
+    fn expr(&mut self, expr: &expr::Expr) {
+        match expr {
+            expr::Expr::Literal(literal) => {
+                let span = literal.span;
+                match &literal.value {
+                    Literal::Nil => self.emit(OpCode::Nil, span),
+                    Literal::Bool(true) => self.emit(OpCode::True, span),
+                    Literal::Bool(false) => self.emit(OpCode::False, span),
+                    Literal::Number(x) => self.emit_constant(Constant::Number(*x), span),
+                    Literal::String(x) => self.emit_constant(Constant::String(x.clone()), span),
+                }
+            }
+            expr::Expr::Grouping(grouping) => self.expr(&grouping.expression),
+            expr::Expr::Binary(_)
+            | expr::Expr::Logical(_)
+            | expr::Expr::Call(_)
+            | expr::Expr::Get(_)
+            | expr::Expr::Index(_) => {
+                let (head, links) = expr.chain();
+                self.expr(head);
+                for link in links {
+                    self.link(link);
+                }
+            }
+            expr::Expr::Unary(unary) => {
+                self.expr(&unary.right);
+                let op = match unary.operator.r#type {
+                    TokenType::Bang => OpCode::Not,
+                    _ => OpCode::Negate,
+                };
+                self.emit(op, unary.operator.span);
+            }
+            expr::Expr::Variable(variable) => {
+                self.get_variable(&variable.name.lexeme, variable.name.span)
+            }
+            expr::Expr::This(this) => self.get_variable("this", this.keyword.span),
+            expr::Expr::Super(super_expr) => {
+                let span = super_expr.method.span;
+                self.get_variable("this", super_expr.keyword.span);
+                self.get_variable("super", super_expr.keyword.span);
+                let name = self.identifier(&super_expr.method.lexeme, span);
+                self.emit(OpCode::GetSuper, span);
+                self.chunk().write_u16(name, span);
+            }
+            expr::Expr::Assign(assign) => {
+                self.expr(&assign.value);
+                let span = assign.name.span;
+                match self.resolve(&assign.name.lexeme, span) {
+                    Access::Local(slot) => {
+                        self.emit(OpCode::SetLocal, span);
+                        self.chunk().write(slot, span);
+                    }
+                    Access::Upvalue(index) => {
+                        self.emit(OpCode::SetUpvalue, span);
+                        self.chunk().write(index, span);
+                    }
+                    Access::Global(name) => {
+                        self.emit(OpCode::SetGlobal, span);
+                        self.chunk().write_u16(name, span);
+                    }
+                }
+            }
+            expr::Expr::Set(set) => {
+                self.expr(&set.object);
+                self.expr(&set.value);
+                let name = self.identifier(&set.name.lexeme, set.name.span);
+                self.emit(OpCode::SetProperty, set.name.span);
+                self.chunk().write_u16(name, set.name.span);
+            }
+            expr::Expr::List(list) => {
+                for element in &list.elements {
+                    self.expr(element);
+                }
+                self.emit_count(OpCode::List, list.elements.len(), list.span);
+            }
+            expr::Expr::Map(map) => {
+                self.emit(OpCode::Map, map.span);
+                for (key, value) in &map.entries {
+                    self.expr(key);
+                    self.expr(value);
+                    self.emit(OpCode::MapEntry, key.span());
+                }
+            }
+            expr::Expr::SetIndex(set_index) => {
+                self.expr(&set_index.object);
+                self.expr(&set_index.index);
+                self.expr(&set_index.value);
+                self.emit(OpCode::SetIndex, set_index.index.span());
+            }
+        }
+    }
+
+    /// Compiles a chain link whose left-hand side is already on the stack.
+    fn link(&mut self, link: &expr::Expr) {
+        match link {
+            expr::Expr::Binary(binary) => {
+                self.expr(&binary.right);
+                let op = match binary.operator.r#type {
+                    TokenType::EqualEqual => OpCode::Equal,
+                    TokenType::BangEqual => OpCode::NotEqual,
+                    TokenType::Greater => OpCode::Greater,
+                    TokenType::GreaterEqual => OpCode::GreaterEqual,
+                    TokenType::Less => OpCode::Less,
+                    TokenType::LessEqual => OpCode::LessEqual,
+                    TokenType::Plus => OpCode::Add,
+                    TokenType::Minus => OpCode::Subtract,
+                    TokenType::Star => OpCode::Multiply,
+                    _ => OpCode::Divide,
+                };
+                self.emit(op, binary.operator.span);
+            }
+            expr::Expr::Logical(logical) => {
+                let span = logical.operator.span;
+                if logical.operator.r#type == TokenType::Or {
+                    let else_jump = self.emit_jump(OpCode::JumpIfFalse, span);
+                    let end_jump = self.emit_jump(OpCode::Jump, span);
+                    self.patch_jump(else_jump, span);
+                    self.emit(OpCode::Pop, span);
+                    self.expr(&logical.right);
+                    self.patch_jump(end_jump, span);
+                } else {
+                    let end_jump = self.emit_jump(OpCode::JumpIfFalse, span);
+                    self.emit(OpCode::Pop, span);
+                    self.expr(&logical.right);
+                    self.patch_jump(end_jump, span);
+                }
+            }
+            expr::Expr::Call(call) => {
+                for argument in &call.arguments {
+                    self.expr(argument);
+                }
+                self.emit(OpCode::Call, call.paren.span);
+                self.chunk()
+                    .write(call.arguments.len() as u8, call.paren.span);
+            }
+            expr::Expr::Get(get) => {
+                let name = self.identifier(&get.name.lexeme, get.name.span);
+                self.emit(OpCode::GetProperty, get.name.span);
+                self.chunk().write_u16(name, get.name.span);
+            }
+            expr::Expr::Index(index) => {
+                self.expr(&index.index);
+                self.emit(OpCode::GetIndex, index.index.span());
+            }
+            _ => unreachable!("Not a chain link"),
+        }
+    }
+
+    fn get_variable(&mut self, name: &str, span: Span) {
+        match self.resolve(name, span) {
+            Access::Local(slot) => {
+                self.emit(OpCode::GetLocal, span);
+                self.chunk().write(slot, span);
+            }
+            Access::Upvalue(index) => {
+                self.emit(OpCode::GetUpvalue, span);
+                self.chunk().write(index, span);
+            }
+            Access::Global(name) => {
+                self.emit(OpCode::GetGlobal, span);
+                self.chunk().write_u16(name, span);
+            }
+        }
+    }
+
+    /// Binds the value on top of the stack to `name`: a new local inside a
+    /// scope, otherwise a global.
+    fn define_variable(&mut self, name: &str, span: Span) {
+        if self.state().scope_depth > 0 {
+            self.add_local(name, span);
+            return;
+        }
+        let constant = self.identifier(name, span);
+        self.emit(OpCode::DefineGlobal, span);
+        self.chunk().write_u16(constant, span);
+    }
+
+    fn resolve(&mut self, name: &str, span: Span) -> Access {
+        let innermost = self.functions.len() - 1;
+        if let Some(slot) = self.resolve_local(innermost, name) {
+            return Access::Local(slot);
+        }
+        if let Some(index) = self.resolve_upvalue(innermost, name, span) {
+            return Access::Upvalue(index);
+        }
+        Access::Global(self.identifier(name, span))
+    }
+
+    fn resolve_local(&self, function: usize, name: &str) -> Option<u8> {
+        let locals = &self.functions[function].locals;
+        locals
+            .iter()
+            .rposition(|local| local.name == name)
+            .map(|slot| slot as u8)
+    }
+
+    /// Finds `name` in the functions enclosing `function`, threading it
+    /// through each intermediate function's upvalues.
+    fn resolve_upvalue(&mut self, function: usize, name: &str, span: Span) -> Option<u8> {
+        if function == 0 {
+            return None;
+        }
+        if let Some(slot) = self.resolve_local(function - 1, name) {
+            self.functions[function - 1].locals[slot as usize].captured = true;
+            return Some(self.add_upvalue(function, slot, true, span));
+        }
+        let index = self.resolve_upvalue(function - 1, name, span)?;
+        Some(self.add_upvalue(function, index, false, span))
+    }
+
+    fn add_upvalue(&mut self, function: usize, index: u8, is_local: bool, span: Span) -> u8 {
+        let upvalue = UpvalueRef { index, is_local };
+        let upvalues = &self.functions[function].upvalues;
+        if let Some(existing) = upvalues.iter().position(|u| *u == upvalue) {
+            return existing as u8;
+        }
+        if upvalues.len() == MAX_SLOTS {
+            self.error(
+                ErrorCode::TooManyUpvalues,
+                span,
+                "Too many closure variables in function",
+            );
+            return 0;
+        }
+        let upvalues = &mut self.functions[function].upvalues;
+        upvalues.push(upvalue);
+        (upvalues.len() - 1) as u8
+    }
+
+    fn add_local(&mut self, name: &str, span: Span) {
+        if self.state().locals.len() == MAX_SLOTS {
+            self.error(
+                ErrorCode::TooManyLocals,
+                span,
+                "Too many local variables in function",
+            );
+            return;
+        }
+        let depth = self.state().scope_depth;
+        self.state().locals.push(Local {
+            name: name.to_string(),
+            depth,
+            captured: false,
+        });
+    }
+
+    fn begin_scope(&mut self) {
+        self.state().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self, span: Span) {
+        self.state().scope_depth -= 1;
+        loop {
+            let state = self.state();
+            let op = match state.locals.last() {
+                Some(local) if local.depth > state.scope_depth => match local.captured {
+                    true => OpCode::CloseUpvalue,
+                    false => OpCode::Pop,
+                },
+                _ => break,
+            };
+            state.locals.pop();
+            self.emit(op, span);
+        }
+    }
+
+    fn state(&mut self) -> &mut FunctionState {
+        let innermost = self.functions.len() - 1;
+        &mut self.functions[innermost]
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.state().proto.chunk
+    }
+
+    fn emit(&mut self, op: OpCode, span: Span) {
+        self.chunk().write_op(op, span);
+    }
+
+    fn emit_constant(&mut self, constant: Constant, span: Span) {
+        let index = self.make_constant(constant, span);
+        self.emit(OpCode::Constant, span);
+        self.chunk().write_u16(index, span);
+    }
+
+    /// Emits an instruction taking an element count, e.g. for a list literal.
+    fn emit_count(&mut self, op: OpCode, count: usize, span: Span) {
+        let count = u16::try_from(count).unwrap_or_else(|_| {
+            self.error(
+                ErrorCode::TooManyConstants,
+                span,
+                "Too many elements in one literal",
+            );
+            0
+        });
+        self.emit(op, span);
+        self.chunk().write_u16(count, span);
+    }
+
+    fn identifier(&mut self, name: &str, span: Span) -> u16 {
+        self.make_constant(Constant::String(Symbol::intern(name)), span)
+    }
+
+    fn make_constant(&mut self, constant: Constant, span: Span) -> u16 {
+        match self.chunk().add_constant(constant) {
+            Some(index) => index,
+            None => {
+                self.error(
+                    ErrorCode::TooManyConstants,
+                    span,
+                    "Too many constants in one chunk",
+                );
+                0
+            }
+        }
+    }
+
+    /// Emits a jump with a placeholder offset, returning where to patch it.
+    fn emit_jump(&mut self, op: OpCode, span: Span) -> usize {
+        self.emit(op, span);
+        self.chunk().write_u16(u16::MAX, span);
+        self.chunk().code.len() - 2
+    }
+
+    /// Points the jump operand at `offset` to the next instruction.
+    fn patch_jump(&mut self, offset: usize, span: Span) {
+        let distance = self.chunk().code.len() - offset - 2;
+        let distance = u16::try_from(distance).unwrap_or_else(|_| {
+            self.error(ErrorCode::JumpTooLarge, span, "Too much code to jump over");
+            0
+        });
+        self.chunk().patch_u16(offset, distance);
+    }
+
+    fn emit_loop(&mut self, start: usize, span: Span) {
+        self.emit(OpCode::Loop, span);
+        let distance = self.chunk().code.len() + 2 - start;
+        let distance = u16::try_from(distance).unwrap_or_else(|_| {
+            self.error(ErrorCode::JumpTooLarge, span, "Loop body too large");
+            0
+        });
+        self.chunk().write_u16(distance, span);
+    }
+
+    /// Records a compile error. A limit that is hit once is usually hit many
+    /// times after, so only the first error of each kind is kept.
+    fn error(&mut self, code: ErrorCode, span: Span, message: &str) {
+        if self.errors.iter().all(|error| error.code() != code) {
+            self.errors.push(LoxError::compile(code, span, message));
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use crate::error::{ErrorCode, LoxError, Span};
+use std::ffi::OsStr;
+use std::fmt::Write;
+use std::io::IsTerminal;
+use std::{env, str};
+
+const RED: &str = "\x1b[1;31m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// Whether rendered diagnostics use ANSI colors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+impl ColorChoice {
+    /// Resolves `Auto` to color only when stderr is a terminal and
+    /// `NO_COLOR` is unset or empty (see https://no-color.org).
+    pub fn enabled(self) -> bool {
+        let no_color = env::var_os("NO_COLOR");
+        self.enabled_for(no_color.as_deref(), std::io::stderr().is_terminal())
+    }
+
+    /// What `enabled` decides given the value of `NO_COLOR` and whether
+    /// stderr is a terminal.
+    pub fn enabled_for(self, no_color: Option<&OsStr>, terminal: bool) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => no_color.is_none_or(OsStr::is_empty) && terminal,
+        }
+    }
+}
+impl str::FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!("Unknown color choice: {}", s)),
+        }
+    }
+}
+
+/// A `LoxError` prepared for display, rustc style: a header with the error
+/// code, the offending source line with the span underlined, then any help.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub code: ErrorCode,
+    pub span: Span,
+    pub message: String,
+    pub help: Option<String>,
+}
+impl Diagnostic {
+    /// Renders the diagnostic against the `source` it was produced from.
+    /// `path` names the source in the location line. With an empty `source`
+    /// the snippet is left out.
+    pub fn render(&self, source: &str, path: &str, color: bool) -> String {
+        let paint = |style: &str, text: &str| match color {
+            true => format!("{}{}{}", style, text, RESET),
+            false => text.to_string(),
+        };
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{}{}",
+            paint(RED, &format!("error[{}]", self.code)),
+            paint(BOLD, &format!(": {}", self.message))
+        );
+
+        let line = match self.span.line {
+            _ if source.is_empty() => None,
+            0 => None,
+            n => source.split('\n').nth(n - 1),
+        };
+        let gutter = " ".repeat(self.span.line.to_string().len());
+        let location = match (self.span.line, self.span.column) {
+            (0, _) => path.to_string(),
+            (line, 0) => format!("{}:{}", path, line),
+            (line, column) => format!("{}:{}:{}", path, line, column),
+        };
+        let _ = writeln!(out, "{}{} {}", gutter, paint(BLUE, "-->"), location);
+
+        if let Some(line) = line {
+            let line = line.trim_end_matches('\r');
+            let bar = paint(BLUE, "|");
+            let _ = writeln!(out, "{} {}", gutter, bar);
+            let _ = writeln!(
+                out,
+                "{} {} {}",
+                paint(BLUE, &self.span.line.to_string()),
+                bar,
+                line
+            );
+            // Reuse tabs from the source line so the underline stays aligned.
+            let padding: String = line
+                .chars()
+                .take(self.span.column.saturating_sub(1))
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            let width = source
+                .get(self.span.start..self.span.end)
+                .and_then(|text| text.split('\n').next())
+                .map_or(0, |text| text.chars().count())
+                .max(1);
+            let _ = writeln!(
+                out,
+                "{} {} {}{}",
+                gutter,
+                bar,
+                padding,
+                paint(RED, &"^".repeat(width))
+            );
+        }
+
+        if let Some(help) = &self.help {
+            let _ = writeln!(
+                out,
+                "{} {} {}: {}",
+                gutter,
+                paint(BLUE, "="),
+                paint(BOLD, "help"),
+                help
+            );
+        }
+        out
+    }
+}
+impl From<&LoxError> for Diagnostic {
+    fn from(error: &LoxError) -> Self {
+        Diagnostic {
+            code: error.code(),
+            span: error.span(),
+            message: error.message().to_string(),
+            help: error.code().help().map(str::to_string),
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use crate::chunk::{Chunk, Constant, FunctionProto, OpCode};
+use crate::value::{self, Quoted};
+use std::fmt::Write;
+
+/// Renders a compiled function and every function nested in it as text, one
+/// instruction per line: byte offset, source line (`|` when unchanged from
+/// the previous instruction), opcode and operands.
+///
+/// ```text
+/// == <script> ==
+/// 0000    1 Constant            0 1
+/// 0003    | Print
+/// 0004    | Nil
+/// 0005    | Return
+/// ```
+pub fn disassemble(function: &FunctionProto) -> String {
+    let mut out = String::new();
+    disassemble_into(&mut out, function);
+    out
+}
+
+fn disassemble_into(out: &mut String, function: &FunctionProto) {
+    let _ = writeln!(out, "== {} ==", function);
+    let chunk = &function.chunk;
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        offset = disassemble_instruction(out, chunk, offset);
+    }
+    for constant in &chunk.constants {
+        if let Constant::Function(nested) = constant {
+            let _ = writeln!(out);
+            disassemble_into(out, nested);
+        }
+    }
+}
+
+/// Writes the instruction at `offset` and returns the offset of the next one.
+pub fn disassemble_instruction(out: &mut String, chunk: &Chunk, offset: usize) -> usize {
+    let _ = write!(out, "{:04} ", offset);
+    let line = chunk.spans[offset].line;
+    match offset > 0 && chunk.spans[offset - 1].line == line {
+        true => out.push_str("   | "),
+        false => {
+            let _ = write!(out, "{:4} ", line);
+        }
+    }
+    let op = match OpCode::try_from(chunk.code[offset]) {
+        Ok(op) => op,
+        Err(byte) => {
+            let _ = writeln!(out, "Unknown opcode {}", byte);
+            return offset + 1;
+        }
+    };
+    let name = format!("{:?}", op);
+    match op {
+        OpCode::Constant
+        | OpCode::GetGlobal
+        | OpCode::DefineGlobal
+        | OpCode::SetGlobal
+        | OpCode::GetProperty
+        | OpCode::SetProperty
+        | OpCode::GetSuper => {
+            let index = chunk.read_u16(offset + 1);
+            let _ = writeln!(out, "{:<16} {:4} {}", name, index, constant(chunk, index));
+            offset + 3
+        }
+        OpCode::GetLocal
+        | OpCode::SetLocal
+        | OpCode::GetUpvalue
+        | OpCode::SetUpvalue
+        | OpCode::Call => {
+            let _ = writeln!(out, "{:<16} {:4}", name, chunk.code[offset + 1]);
+            offset + 2
+        }
+        OpCode::List => {
+            let _ = writeln!(out, "{:<16} {:4}", name, chunk.read_u16(offset + 1));
+            offset + 3
+        }
+        OpCode::Jump | OpCode::JumpIfFalse | OpCode::Loop => {
+            let distance = chunk.read_u16(offset + 1) as usize;
+            let target = match op {
+                OpCode::Loop => (offset + 3).wrapping_sub(distance),
+                _ => offset + 3 + distance,
+            };
+            let _ = writeln!(out, "{:<16} {:4} -> {:04}", name, offset, target);
+            offset + 3
+        }
+        OpCode::Closure => {
+            let index = chunk.read_u16(offset + 1);
+            let _ = writeln!(out, "{:<16} {:4} {}", name, index, constant(chunk, index));
+            let upvalues = match chunk.constants.get(index as usize) {
+                Some(Constant::Function(function)) => function.upvalue_count,
+                _ => 0,
+            };
+            let mut next = offset + 3;
+            for _ in 0..upvalues {
+                let kind = match chunk.code[next] {
+                    1 => "local",
+                    _ => "upvalue",
+                };
+                let _ = writeln!(out, "{:04}    |   {} {}", next, kind, chunk.code[next + 1]);
+                next += 2;
+            }
+            next
+        }
+        OpCode::Class => {
+            let index = chunk.read_u16(offset + 1);
+            let superclass = match chunk.code[offset + 3] {
+                1 => " < super",
+                _ => "",
+            };
+            let count = chunk.read_u16(offset + 4) as usize;
+            let methods: Vec<String> = (0..count)
+                .map(|i| constant(chunk, chunk.read_u16(offset + 6 + 2 * i)))
+                .collect();
+            let _ = writeln!(
+                out,
+                "{:<16} {:4} {}{} [{}]",
+                name,
+                index,
+                constant(chunk, index),
+                superclass,
+                methods.join(", ")
+            );
+            offset + 6 + 2 * count
+        }
+        _ => {
+            let _ = writeln!(out, "{}", name);
+            offset + 1
+        }
+    }
+}
+
+fn constant(chunk: &Chunk, index: u16) -> String {
+    match chunk.constants.get(index as usize) {
+        Some(Constant::Number(x)) => value::format_number(*x),
+        Some(Constant::String(x)) => format!("{}", Quoted(x)),
+        Some(Constant::Function(function)) => format!("{}", function),
+        None => "<invalid constant>".to_string(),
+    }
+}
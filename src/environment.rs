@@ -1,57 +1,86 @@
-use crate::expr;
+use crate::error::{ErrorCode, LoxError};
+use crate::gc::{self, Trace};
 use crate::token_type;
-use std::collections::HashMap;
-use std::{cell::RefCell, error::Error, fmt, rc::Rc};
-
-#[derive(Debug)]
-pub struct RuntimeError {
-    pub description: String,
-}
-impl Error for RuntimeError {}
-impl fmt::Display for RuntimeError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.description)
-    }
-}
+use crate::value::{self, Value};
+use std::{cell::RefCell, rc::Rc};
 
+/// The local variables of one scope. Each is stored in the slot the resolver
+/// assigned it, in declaration order, so lookups index instead of hashing.
+/// Globals live in `Interpreter::globals` instead.
 pub struct Environment {
     pub enclosing: Option<Rc<RefCell<Environment>>>,
-    pub values: HashMap<String, expr::Literal>,
+    pub slots: Vec<Value>,
 }
 impl Environment {
-    pub fn define(&mut self, name: String, value: Option<expr::Literal>) {
-        match value {
-            Some(value) => self.values.insert(name, value),
-            None => self.values.insert(name, expr::Literal::Nil),
-        };
+    pub fn new(enclosing: Option<Rc<RefCell<Environment>>>) -> Self {
+        Environment {
+            enclosing,
+            slots: vec![],
+        }
+    }
+
+    /// Declares the scope's next variable, which takes the next slot.
+    pub fn define(&mut self, value: Value) {
+        self.slots.push(value);
+    }
+
+    /// Reads `slot` from the environment exactly `distance` hops up the
+    /// chain, as computed by the resolver.
+    pub fn get_at(&self, distance: usize, slot: usize) -> Option<Value> {
+        if distance == 0 {
+            return self.slots.get(slot).cloned();
+        }
+        self.enclosing
+            .as_ref()
+            .and_then(|enclosing| enclosing.borrow().get_at(distance - 1, slot))
     }
 
-    pub fn assign(
-        &mut self,
-        name: token_type::Token,
-        value: expr::Literal,
-    ) -> Result<(), RuntimeError> {
-        if self.values.contains_key(&name.lexeme) {
-            self.values.insert(name.lexeme, value);
-            return Ok(());
+    pub fn assign_at(&mut self, distance: usize, slot: usize, value: Value) -> bool {
+        if distance == 0 {
+            return match self.slots.get_mut(slot) {
+                Some(variable) => {
+                    *variable = value;
+                    true
+                }
+                None => false,
+            };
         }
-        if let Some(enclosing) = &mut self.enclosing {
-            return enclosing.borrow_mut().assign(name, value);
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow_mut().assign_at(distance - 1, slot, value),
+            None => false,
         }
-        Err(RuntimeError {
-            description: format!("Undefined variable {}", name.lexeme),
-        })
     }
 
-    pub fn get(&self, name: token_type::Token) -> Result<expr::Literal, RuntimeError> {
-        if self.values.contains_key(&name.lexeme) {
-            return Ok(self.values.get(&name.lexeme).unwrap().clone());
+    pub fn undefined(name: &token_type::Token) -> LoxError {
+        LoxError::runtime(
+            ErrorCode::UndefinedVariable,
+            name.span,
+            format!("Undefined variable {}", name.lexeme),
+        )
+    }
+}
+impl Trace for RefCell<Environment> {
+    fn trace(&self, visit: &mut dyn FnMut(usize)) {
+        if let Ok(environment) = self.try_borrow() {
+            if let Some(enclosing) = &environment.enclosing {
+                visit(gc::id(enclosing));
+            }
+            environment
+                .slots
+                .iter()
+                .for_each(|value| value.trace(visit));
         }
-        if let Some(enclosing) = &self.enclosing {
-            return enclosing.borrow_mut().get(name);
+    }
+
+    fn clear(&self) {
+        if let Ok(mut environment) = self.try_borrow_mut() {
+            environment.enclosing = None;
+            environment.slots.clear();
         }
-        Err(RuntimeError {
-            description: format!("Undefined variable {}", name.lexeme),
-        })
+    }
+}
+impl Drop for Environment {
+    fn drop(&mut self) {
+        value::release(std::mem::take(&mut self.slots));
     }
 }
@@ -0,0 +1,293 @@
+use std::{error, fmt};
+
+/// A location in the source. `start..end` is a byte range, `line` and
+/// `column` are 1-based and point at `start`. A column of 0 means unknown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub start: usize,
+    pub end: usize,
+}
+impl Span {
+    /// The smallest span covering both `self` and a later span `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            end: other.end.max(self.end),
+            ..self
+        }
+    }
+}
+
+/// Identifies the kind of an error independently of its message. Codes are
+/// grouped by phase: E01xx scan, E02xx parse, E03xx resolve, E04xx runtime,
+/// E05xx bytecode compilation.
+/// Existing codes must never be renumbered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    UnexpectedCharacter,
+    UnterminatedString,
+    InvalidNumber,
+
+    ExpectedToken,
+    ExpectedExpression,
+    InvalidAssignmentTarget,
+    TooManyParameters,
+    TooManyArguments,
+    TooDeeplyNested,
+
+    SelfReferencingInitializer,
+    DuplicateDeclaration,
+    TopLevelReturn,
+    InitializerReturn,
+    ThisOutsideClass,
+    SuperOutsideClass,
+    SuperWithoutSuperclass,
+    SelfInheritance,
+    BreakOutsideLoop,
+    ContinueOutsideLoop,
+
+    InvalidOperand,
+    UndefinedVariable,
+    UndefinedProperty,
+    NotCallable,
+    ArityMismatch,
+    NotAnInstance,
+    SuperclassNotClass,
+    NativeError,
+    Io,
+    StackOverflow,
+    NotIndexable,
+    InvalidIndex,
+    IndexOutOfBounds,
+    UndefinedKey,
+    InvalidKey,
+    StepLimitExceeded,
+
+    TooManyConstants,
+    TooManyLocals,
+    TooManyUpvalues,
+    JumpTooLarge,
+    InvalidBytecode,
+}
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::UnexpectedCharacter => "E0101",
+            ErrorCode::UnterminatedString => "E0102",
+            ErrorCode::InvalidNumber => "E0103",
+
+            ErrorCode::ExpectedToken => "E0201",
+            ErrorCode::ExpectedExpression => "E0202",
+            ErrorCode::InvalidAssignmentTarget => "E0203",
+            ErrorCode::TooManyParameters => "E0204",
+            ErrorCode::TooManyArguments => "E0205",
+            ErrorCode::TooDeeplyNested => "E0206",
+
+            ErrorCode::SelfReferencingInitializer => "E0301",
+            ErrorCode::DuplicateDeclaration => "E0302",
+            ErrorCode::TopLevelReturn => "E0303",
+            ErrorCode::InitializerReturn => "E0304",
+            ErrorCode::ThisOutsideClass => "E0305",
+            ErrorCode::SuperOutsideClass => "E0306",
+            ErrorCode::SuperWithoutSuperclass => "E0307",
+            ErrorCode::SelfInheritance => "E0308",
+            ErrorCode::BreakOutsideLoop => "E0309",
+            ErrorCode::ContinueOutsideLoop => "E0310",
+
+            ErrorCode::InvalidOperand => "E0401",
+            ErrorCode::UndefinedVariable => "E0402",
+            ErrorCode::UndefinedProperty => "E0403",
+            ErrorCode::NotCallable => "E0404",
+            ErrorCode::ArityMismatch => "E0405",
+            ErrorCode::NotAnInstance => "E0406",
+            ErrorCode::SuperclassNotClass => "E0407",
+            ErrorCode::NativeError => "E0408",
+            ErrorCode::Io => "E0409",
+            ErrorCode::StackOverflow => "E0410",
+            ErrorCode::NotIndexable => "E0411",
+            ErrorCode::InvalidIndex => "E0412",
+            ErrorCode::IndexOutOfBounds => "E0413",
+            ErrorCode::UndefinedKey => "E0414",
+            ErrorCode::InvalidKey => "E0415",
+            ErrorCode::StepLimitExceeded => "E0416",
+
+            ErrorCode::TooManyConstants => "E0501",
+            ErrorCode::TooManyLocals => "E0502",
+            ErrorCode::TooManyUpvalues => "E0503",
+            ErrorCode::JumpTooLarge => "E0504",
+            ErrorCode::InvalidBytecode => "E0505",
+        }
+    }
+}
+impl ErrorCode {
+    /// A suggestion shown under diagnostics with this code, if one applies
+    /// regardless of context.
+    pub fn help(&self) -> Option<&'static str> {
+        match self {
+            ErrorCode::UnterminatedString => Some("add a closing '\"' to end the string"),
+            ErrorCode::InvalidAssignmentTarget => {
+                Some("only variables and properties can be assigned to")
+            }
+            ErrorCode::SelfReferencingInitializer => {
+                Some("declare the variable first, then assign to it")
+            }
+            ErrorCode::DuplicateDeclaration => {
+                Some("use a different name, or assign to the existing variable")
+            }
+            ErrorCode::TopLevelReturn => Some("'return' is only valid inside a function"),
+            ErrorCode::InitializerReturn => {
+                Some("'init' always returns the instance; use a bare 'return;'")
+            }
+            ErrorCode::ThisOutsideClass => Some("'this' is only valid inside a method"),
+            ErrorCode::SuperWithoutSuperclass => {
+                Some("declare a superclass with 'class Name < Superclass'")
+            }
+            ErrorCode::UndefinedVariable => Some("declare it with 'var' before using it"),
+            ErrorCode::NotCallable => Some("only functions and classes can be called"),
+            ErrorCode::StackOverflow => Some("check for recursion without a base case"),
+            ErrorCode::UndefinedKey => Some("use has() to check for a key first"),
+            ErrorCode::InvalidBytecode => Some("recompile the script with 'rlox compile'"),
+            _ => None,
+        }
+    }
+}
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// An error from any phase of running a script.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoxError {
+    Scan {
+        code: ErrorCode,
+        span: Span,
+        message: String,
+    },
+    Parse {
+        code: ErrorCode,
+        span: Span,
+        message: String,
+    },
+    Resolve {
+        code: ErrorCode,
+        span: Span,
+        message: String,
+    },
+    Runtime {
+        code: ErrorCode,
+        span: Span,
+        message: String,
+    },
+    Compile {
+        code: ErrorCode,
+        span: Span,
+        message: String,
+    },
+}
+impl LoxError {
+    pub fn scan(code: ErrorCode, span: Span, message: impl Into<String>) -> Self {
+        LoxError::Scan {
+            code,
+            span,
+            message: message.into(),
+        }
+    }
+
+    pub fn parse(code: ErrorCode, span: Span, message: impl Into<String>) -> Self {
+        LoxError::Parse {
+            code,
+            span,
+            message: message.into(),
+        }
+    }
+
+    pub fn resolve(code: ErrorCode, span: Span, message: impl Into<String>) -> Self {
+        LoxError::Resolve {
+            code,
+            span,
+            message: message.into(),
+        }
+    }
+
+    pub fn runtime(code: ErrorCode, span: Span, message: impl Into<String>) -> Self {
+        LoxError::Runtime {
+            code,
+            span,
+            message: message.into(),
+        }
+    }
+
+    pub fn compile(code: ErrorCode, span: Span, message: impl Into<String>) -> Self {
+        LoxError::Compile {
+            code,
+            span,
+            message: message.into(),
+        }
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        self.parts().0
+    }
+
+    pub fn span(&self) -> Span {
+        self.parts().1
+    }
+
+    pub fn message(&self) -> &str {
+        self.parts().2
+    }
+
+    /// Process exit code following sysexits.h: 65 (EX_DATAERR) for errors
+    /// found before running, 70 (EX_SOFTWARE) for runtime errors.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            LoxError::Runtime { .. } => 70,
+            _ => 65,
+        }
+    }
+
+    fn parts(&self) -> (ErrorCode, Span, &str) {
+        match self {
+            LoxError::Scan {
+                code,
+                span,
+                message,
+            }
+            | LoxError::Parse {
+                code,
+                span,
+                message,
+            }
+            | LoxError::Resolve {
+                code,
+                span,
+                message,
+            }
+            | LoxError::Runtime {
+                code,
+                span,
+                message,
+            }
+            | LoxError::Compile {
+                code,
+                span,
+                message,
+            } => (*code, *span, message),
+        }
+    }
+}
+impl fmt::Display for LoxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[line {}] Error[{}]: {}",
+            self.span().line,
+            self.code(),
+            self.message()
+        )
+    }
+}
+impl error::Error for LoxError {}
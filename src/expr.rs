@@ -1,19 +1,137 @@
+use crate::error::Span;
+use crate::resolver::Slot;
+use crate::symbol::Symbol;
 use crate::token_type::Token;
+use crate::value;
+use std::cell::Cell;
 use std::fmt;
 
 #[derive(Clone)]
 pub enum Expr {
     Binary(Box<Binary>),
     Grouping(Box<Grouping>),
-    Literal(Literal),
+    Literal(Box<LiteralExpr>),
     Unary(Box<Unary>),
     Variable(Box<Variable>),
     Assign(Box<Assign>),
     Logical(Box<Logical>),
+    Call(Box<Call>),
+    Get(Box<Get>),
+    Set(Box<Set>),
+    This(Box<This>),
+    Super(Box<Super>),
+    List(Box<List>),
+    Map(Box<Map>),
+    Index(Box<Index>),
+    SetIndex(Box<SetIndex>),
+}
+impl Expr {
+    /// The source range the expression was parsed from.
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Binary(x) => x.span,
+            Expr::Grouping(x) => x.span,
+            Expr::Literal(x) => x.span,
+            Expr::Unary(x) => x.span,
+            Expr::Variable(x) => x.span,
+            Expr::Assign(x) => x.span,
+            Expr::Logical(x) => x.span,
+            Expr::Call(x) => x.span,
+            Expr::Get(x) => x.span,
+            Expr::Set(x) => x.span,
+            Expr::This(x) => x.span,
+            Expr::Super(x) => x.span,
+            Expr::List(x) => x.span,
+            Expr::Map(x) => x.span,
+            Expr::Index(x) => x.span,
+            Expr::SetIndex(x) => x.span,
+        }
+    }
+
+    /// The left-hand side of a chain link: the left operand of a binary or
+    /// logical operator, or the callee or object of a call, property access
+    /// or index. Chains nest as deeply as they are long, so passes over the
+    /// tree follow these in a loop instead of recursing.
+    pub fn chain_left(&self) -> Option<&Expr> {
+        match self {
+            Expr::Binary(x) => Some(&x.left),
+            Expr::Logical(x) => Some(&x.left),
+            Expr::Call(x) => Some(&x.callee),
+            Expr::Get(x) => Some(&x.object),
+            Expr::Index(x) => Some(&x.object),
+            _ => None,
+        }
+    }
+
+    fn chain_left_mut(&mut self) -> Option<&mut Expr> {
+        match self {
+            Expr::Binary(x) => Some(&mut x.left),
+            Expr::Logical(x) => Some(&mut x.left),
+            Expr::Call(x) => Some(&mut x.callee),
+            Expr::Get(x) => Some(&mut x.object),
+            Expr::Index(x) => Some(&mut x.object),
+            _ => None,
+        }
+    }
+
+    /// Splits the chain this expression ends into the operand it starts
+    /// from and its links, innermost first.
+    pub fn chain(&self) -> (&Expr, Vec<&Expr>) {
+        let mut links = vec![];
+        let mut head = self;
+        while let Some(left) = head.chain_left() {
+            links.push(head);
+            head = left;
+        }
+        links.reverse();
+        (head, links)
+    }
+
+    /// Moves the expression out, leaving `nil` in its place.
+    pub fn take(&mut self) -> Expr {
+        let nil = Expr::Literal(Box::new(LiteralExpr {
+            value: Literal::Nil,
+            span: self.span(),
+        }));
+        std::mem::replace(self, nil)
+    }
+}
+
+/// Drops the chain below a link one link at a time, since dropping it
+/// recursively would take a stack frame per link.
+fn drop_chain(left: &mut Expr) {
+    if left.chain_left().is_none() {
+        return;
+    }
+    let mut link = left.take();
+    while let Some(left) = link.chain_left_mut() {
+        if left.chain_left().is_none() {
+            return;
+        }
+        let next = left.take();
+        link = next;
+    }
 }
 impl fmt::Display for Expr {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", *self)
+        match self {
+            Expr::Binary(x) => write!(f, "{}", x),
+            Expr::Grouping(x) => write!(f, "{}", x),
+            Expr::Literal(x) => write!(f, "{}", x),
+            Expr::Unary(x) => write!(f, "{}", x),
+            Expr::Variable(x) => write!(f, "{}", x),
+            Expr::Assign(x) => write!(f, "{}", x),
+            Expr::Logical(x) => write!(f, "{}", x),
+            Expr::Call(x) => write!(f, "{}", x),
+            Expr::Get(x) => write!(f, "{}", x),
+            Expr::Set(x) => write!(f, "{}", x),
+            Expr::This(x) => write!(f, "{}", x),
+            Expr::Super(x) => write!(f, "{}", x),
+            Expr::List(x) => write!(f, "{}", x),
+            Expr::Map(x) => write!(f, "{}", x),
+            Expr::Index(x) => write!(f, "{}", x),
+            Expr::SetIndex(x) => write!(f, "{}", x),
+        }
     }
 }
 
@@ -22,6 +140,12 @@ pub struct Binary {
     pub left: Expr,
     pub operator: Token,
     pub right: Expr,
+    pub span: Span,
+}
+impl Drop for Binary {
+    fn drop(&mut self) {
+        drop_chain(&mut self.left);
+    }
 }
 impl fmt::Display for Binary {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -32,6 +156,7 @@ impl fmt::Display for Binary {
 #[derive(Clone)]
 pub struct Grouping {
     pub expression: Expr,
+    pub span: Span,
 }
 impl fmt::Display for Grouping {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -43,6 +168,7 @@ impl fmt::Display for Grouping {
 pub struct Unary {
     pub operator: Token,
     pub right: Expr,
+    pub span: Span,
 }
 impl fmt::Display for Unary {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -50,10 +176,21 @@ impl fmt::Display for Unary {
     }
 }
 
+#[derive(Clone)]
+pub struct LiteralExpr {
+    pub value: Literal,
+    pub span: Span,
+}
+impl fmt::Display for LiteralExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.value)
+    }
+}
+
 #[derive(Clone)]
 pub enum Literal {
     Number(f64),
-    String(String),
+    String(Symbol),
     Bool(bool),
     Nil,
 }
@@ -61,7 +198,7 @@ impl fmt::Display for Literal {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Literal::Number(x) => {
-                write!(f, "{}", x)
+                write!(f, "{}", value::format_number(*x))
             }
             Literal::String(x) => {
                 write!(f, "{}", x)
@@ -70,7 +207,7 @@ impl fmt::Display for Literal {
                 write!(f, "{}", x)
             }
             Literal::Nil => {
-                write!(f, "Nil")
+                write!(f, "nil")
             }
         }
     }
@@ -78,7 +215,9 @@ impl fmt::Display for Literal {
 
 #[derive(Clone)]
 pub struct Variable {
+    pub slot: Cell<Option<Slot>>, // set by the resolver, None for globals
     pub name: Token,
+    pub span: Span,
 }
 impl fmt::Display for Variable {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -88,8 +227,10 @@ impl fmt::Display for Variable {
 
 #[derive(Clone)]
 pub struct Assign {
+    pub slot: Cell<Option<Slot>>,
     pub name: Token,
     pub value: Expr,
+    pub span: Span,
 }
 impl fmt::Display for Assign {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -102,9 +243,152 @@ pub struct Logical {
     pub left: Expr,
     pub operator: Token,
     pub right: Expr,
+    pub span: Span,
+}
+impl Drop for Logical {
+    fn drop(&mut self) {
+        drop_chain(&mut self.left);
+    }
 }
 impl fmt::Display for Logical {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "({} {} {})", self.operator, self.left, self.right)
     }
 }
+
+#[derive(Clone)]
+pub struct Call {
+    pub callee: Expr,
+    pub paren: Token,
+    pub arguments: Vec<Expr>,
+    pub span: Span,
+}
+impl Drop for Call {
+    fn drop(&mut self) {
+        drop_chain(&mut self.callee);
+    }
+}
+impl fmt::Display for Call {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "(call {}", self.callee)?;
+        for argument in &self.arguments {
+            write!(f, " {}", argument)?;
+        }
+        write!(f, ")")
+    }
+}
+
+#[derive(Clone)]
+pub struct Get {
+    pub object: Expr,
+    pub name: Token,
+    pub span: Span,
+}
+impl Drop for Get {
+    fn drop(&mut self) {
+        drop_chain(&mut self.object);
+    }
+}
+impl fmt::Display for Get {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "(. {} {})", self.object, self.name)
+    }
+}
+
+#[derive(Clone)]
+pub struct Set {
+    pub object: Expr,
+    pub name: Token,
+    pub value: Expr,
+    pub span: Span,
+}
+impl fmt::Display for Set {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "(= {}.{} {})", self.object, self.name, self.value)
+    }
+}
+
+#[derive(Clone)]
+pub struct This {
+    pub slot: Cell<Option<Slot>>,
+    pub keyword: Token,
+    pub span: Span,
+}
+impl fmt::Display for This {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "this")
+    }
+}
+
+#[derive(Clone)]
+pub struct Super {
+    pub slot: Cell<Option<Slot>>, // of 'super'
+    pub keyword: Token,
+    pub method: Token,
+    pub span: Span,
+}
+impl fmt::Display for Super {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "(super {})", self.method)
+    }
+}
+
+#[derive(Clone)]
+pub struct List {
+    pub elements: Vec<Expr>,
+    pub span: Span,
+}
+impl fmt::Display for List {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "(list")?;
+        for element in &self.elements {
+            write!(f, " {}", element)?;
+        }
+        write!(f, ")")
+    }
+}
+
+#[derive(Clone)]
+pub struct Map {
+    pub entries: Vec<(Expr, Expr)>,
+    pub span: Span,
+}
+impl fmt::Display for Map {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "(map")?;
+        for (key, value) in &self.entries {
+            write!(f, " {} {}", key, value)?;
+        }
+        write!(f, ")")
+    }
+}
+
+#[derive(Clone)]
+pub struct Index {
+    pub object: Expr,
+    pub index: Expr,
+    pub span: Span,
+}
+impl Drop for Index {
+    fn drop(&mut self) {
+        drop_chain(&mut self.object);
+    }
+}
+impl fmt::Display for Index {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "([] {} {})", self.object, self.index)
+    }
+}
+
+#[derive(Clone)]
+pub struct SetIndex {
+    pub object: Expr,
+    pub index: Expr,
+    pub value: Expr,
+    pub span: Span,
+}
+impl fmt::Display for SetIndex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "(= {}[{}] {})", self.object, self.index, self.value)
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use std::{
+    cell::RefCell,
+    rc::{Rc, Weak},
+};
+
+/// How many objects may be allocated before the first collection.
+const INITIAL_THRESHOLD: usize = 1024;
+
+thread_local! {
+    static HEAP: RefCell<Heap> = RefCell::new(Heap {
+        objects: vec![],
+        next_gc: INITIAL_THRESHOLD,
+        stress: false,
+    });
+}
+
+/// A heap object that can refer to other heap objects, and so be part of a
+/// reference cycle that `Rc` alone would never free.
+pub trait Trace {
+    /// Calls `visit` with the `id` of every heap object this one refers to.
+    /// An object that is currently borrowed mutably reports nothing.
+    fn trace(&self, visit: &mut dyn FnMut(usize));
+
+    /// Drops this object's references to other objects. Called on
+    /// unreachable objects to break the cycles keeping them alive.
+    fn clear(&self) {}
+}
+
+/// Every object allocated through `alloc`. Objects are still owned by the
+/// `Rc`s pointing at them; the heap only watches them.
+struct Heap {
+    objects: Vec<Weak<dyn Trace>>,
+    next_gc: usize, // collect once this many objects are registered
+    stress: bool,   // collect on every allocation
+}
+
+/// Allocates an object that the collector keeps track of, collecting first
+/// if enough objects have been allocated since the last collection.
+pub fn alloc<T: Trace + 'static>(object: T) -> Rc<T> {
+    let object = Rc::new(object);
+    let due = HEAP.with(|heap| {
+        let heap = heap.borrow();
+        heap.stress || heap.objects.len() >= heap.next_gc
+    });
+    if due {
+        collect();
+    }
+    let weak: Weak<dyn Trace> = Rc::downgrade(&object) as Weak<dyn Trace>;
+    HEAP.with(|heap| heap.borrow_mut().objects.push(weak));
+    object
+}
+
+/// Identifies a heap object, for `Trace::trace`.
+pub fn id<T: ?Sized>(object: &Rc<T>) -> usize {
+    Rc::as_ptr(object) as *const () as usize
+}
+
+/// Makes every allocation collect, to flush out objects that are in use
+/// but unreachable from the collector's roots.
+pub fn set_stress(stress: bool) {
+    HEAP.with(|heap| heap.borrow_mut().stress = stress);
+}
+
+/// Frees every object that is only reachable through reference cycles and
+/// returns how many there were.
+///
+/// This is a mark-and-sweep pass over the tracked objects. The roots are the
+/// objects referenced from outside the heap: the environment chain, the call
+/// stack and VM stack, globals, and values held by Rust code. They are found
+/// by counting each object's references from other tracked objects; any
+/// remaining strong references must come from outside. Everything reachable
+/// from a root is marked, and the rest is cleared, which breaks its cycles so
+/// `Rc` can free it.
+pub fn collect() -> usize {
+    let objects: Vec<Rc<dyn Trace>> = HEAP.with(|heap| {
+        let heap = heap.borrow();
+        heap.objects.iter().filter_map(Weak::upgrade).collect()
+    });
+    let index: HashMap<usize, usize> = objects
+        .iter()
+        .enumerate()
+        .map(|(i, object)| (id(object), i))
+        .collect();
+
+    let mut internal = vec![0; objects.len()];
+    for object in &objects {
+        object.trace(&mut |child| {
+            if let Some(&i) = index.get(&child) {
+                internal[i] += 1;
+            }
+        });
+    }
+    // `objects` itself holds one reference to each
+    let mut marked: Vec<bool> = objects
+        .iter()
+        .zip(&internal)
+        .map(|(object, internal)| Rc::strong_count(object) > internal + 1)
+        .collect();
+    let mut pending: Vec<usize> = (0..objects.len()).filter(|&i| marked[i]).collect();
+    while let Some(i) = pending.pop() {
+        objects[i].trace(&mut |child| {
+            if let Some(&child) = index.get(&child) {
+                if !marked[child] {
+                    marked[child] = true;
+                    pending.push(child);
+                }
+            }
+        });
+    }
+
+    let mut live = vec![];
+    let mut freed = 0;
+    for (object, marked) in objects.iter().zip(marked) {
+        match marked {
+            true => live.push(Rc::downgrade(object)),
+            false => {
+                object.clear();
+                freed += 1;
+            }
+        }
+    }
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.next_gc = INITIAL_THRESHOLD.max(live.len() * 2);
+        heap.objects = live;
+    });
+    freed
+}
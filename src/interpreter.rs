@@ -1,236 +1,655 @@
+use crate::callable::{Callable, LoxFunction};
+use crate::class::{LoxClass, LoxInstance, Method};
 use crate::environment;
+use crate::error::{ErrorCode, LoxError, Span};
 use crate::expr;
+use crate::gc;
+use crate::native::{self, NativeFunction};
+use crate::resolver::Slot;
 use crate::stmt;
-use crate::token_type::TokenType;
-use std::{cell::RefCell, collections::HashMap, error::Error, fmt, rc::Rc};
+use crate::symbol::Symbol;
+use crate::token_type::{self, TokenType};
+use crate::value::{self, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::{cell::RefCell, io, rc::Rc};
+
+/// How many Lox calls may be active at once. Calls recurse on the Rust
+/// stack, so deeper recursion is reported as an error instead of crashing.
+const MAX_CALL_DEPTH: usize = 256;
 
 pub struct Interpreter {
-    pub environment: Rc<RefCell<environment::Environment>>,
+    pub globals: HashMap<Symbol, Value>,
+    pub environment: Option<Rc<RefCell<environment::Environment>>>, // innermost local scope
+    output: Box<dyn io::Write>, // where `print` writes, stdout unless replaced
+    call_depth: usize,
+    step_limit: Option<usize>, // statements one `interpret_stmts` may run
+    steps_left: usize,
 }
 impl Interpreter {
-    pub fn interpret_stmts(&mut self, stmts: &Vec<stmt::Stmt>) -> Result<(), InterpreterError> {
+    pub fn new() -> Self {
+        let mut interpreter = Interpreter {
+            globals: HashMap::new(),
+            environment: None,
+            output: Box::new(io::stdout()),
+            call_depth: 0,
+            step_limit: None,
+            steps_left: 0,
+        };
+        for (name, arity, function) in native::BUILTINS {
+            interpreter.define_native(name, arity, function);
+        }
+        interpreter
+    }
+
+    /// Redirects `print` output, e.g. to a file or an `output::SharedBuffer`.
+    /// Returns the previous sink.
+    pub fn set_output(&mut self, output: Box<dyn io::Write>) -> Box<dyn io::Write> {
+        std::mem::replace(&mut self.output, output)
+    }
+
+    /// Makes `interpret_stmts` fail once a script has run `limit`
+    /// statements, e.g. to run untrusted code that may loop forever. `None`
+    /// removes the limit.
+    pub fn set_step_limit(&mut self, limit: Option<usize>) {
+        self.step_limit = limit;
+    }
+
+    /// Exposes a Rust function to scripts as a global named `name`.
+    pub fn define_native<F>(&mut self, name: &str, arity: usize, function: F)
+    where
+        F: Fn(&[Value]) -> Result<Value, String> + 'static,
+    {
+        let native = NativeFunction {
+            name: name.to_string(),
+            arity,
+            function: Box::new(function),
+        };
+        self.globals
+            .insert(Symbol::intern(name), Value::Native(Rc::new(native)));
+    }
+
+    /// Runs top-level statements. When the last one is an expression
+    /// statement its value is returned, otherwise `Value::Nil`.
+    pub fn interpret_stmts(&mut self, stmts: &[stmt::Stmt]) -> Result<Value, LoxError> {
+        self.steps_left = self.step_limit.unwrap_or(usize::MAX);
+        let mut value = Value::Nil;
         for stmt in stmts {
-            self.stmt(&stmt)?;
+            value = match stmt {
+                stmt::Stmt::Expr(expr_stmt) => self.expr(&expr_stmt.expression)?,
+                _ => match self.stmt(stmt) {
+                    Ok(_) => Value::Nil,
+                    Err(unwind) => return Err(unwind.into_error()),
+                },
+            };
         }
-        Ok(())
+        Ok(value)
     }
 
-    pub fn stmt(&mut self, stmt: &stmt::Stmt) -> Result<(), InterpreterError> {
+    pub fn stmt(&mut self, stmt: &stmt::Stmt) -> Result<(), Unwind> {
+        if self.steps_left == 0 {
+            return Err(LoxError::runtime(
+                ErrorCode::StepLimitExceeded,
+                stmt.span(),
+                "Step limit exceeded",
+            )
+            .into());
+        }
+        self.steps_left -= 1;
         match stmt {
             stmt::Stmt::Expr(expr_stmt) => {
                 self.expr(&expr_stmt.expression)?;
             }
             stmt::Stmt::Print(print_stmt) => self.print_stmt(&print_stmt.expression)?,
             stmt::Stmt::VarDec(var_stmt) => self.var_stmt(var_stmt)?,
-            stmt::Stmt::Block(block_stmt) => self.block_stmt(&block_stmt.statements)?,
+            stmt::Stmt::Block(block_stmt) => self.block_stmt(block_stmt)?,
             stmt::Stmt::If(if_stmt) => self.if_stmt(if_stmt)?,
             stmt::Stmt::While(while_stmt) => self.while_stmt(while_stmt)?,
+            stmt::Stmt::Function(function_stmt) => self.function_stmt(function_stmt),
+            stmt::Stmt::Return(return_stmt) => self.return_stmt(return_stmt)?,
+            stmt::Stmt::Class(class_stmt) => self.class_stmt(class_stmt)?,
+            stmt::Stmt::Break(break_stmt) => return Err(Unwind::Break(break_stmt.span)),
+            stmt::Stmt::Continue(continue_stmt) => {
+                return Err(Unwind::Continue(continue_stmt.span))
+            }
         };
         Ok(())
     }
 
-    pub fn while_stmt(&mut self, stmt: &stmt::While) -> Result<(), InterpreterError> {
-        while Self::is_truthy(&self.expr(&stmt.condition)?) {
-            self.stmt(&stmt.body)?;
+    pub fn while_stmt(&mut self, stmt: &stmt::While) -> Result<(), Unwind> {
+        while self.expr(&stmt.condition)?.is_truthy() {
+            match self.stmt(&stmt.body) {
+                Ok(()) | Err(Unwind::Continue(_)) => {}
+                Err(Unwind::Break(_)) => break,
+                Err(unwind) => return Err(unwind),
+            }
+            if let Some(increment) = &stmt.increment {
+                self.expr(increment)?;
+            }
         }
         Ok(())
     }
 
-    pub fn if_stmt(&mut self, stmt: &stmt::If) -> Result<(), InterpreterError> {
-        if Self::is_truthy(&self.expr(&stmt.condition)?) {
-            return self.stmt(&stmt.then_branch);
-        } else if stmt.else_branch.is_some() {
-            return self.stmt(&stmt.else_branch.as_ref().unwrap());
+    pub fn if_stmt(&mut self, stmt: &stmt::If) -> Result<(), Unwind> {
+        // Follows an `else if` ladder in place: `If::ladder` allocates
+        let mut stmt = stmt;
+        loop {
+            if self.expr(&stmt.condition)?.is_truthy() {
+                return self.stmt(&stmt.then_branch);
+            }
+            match stmt.else_branch.as_deref() {
+                Some(stmt::Stmt::If(else_if)) => stmt = else_if,
+                Some(else_branch) => return self.stmt(else_branch),
+                None => return Ok(()),
+            }
         }
-        Ok(())
     }
 
-    pub fn block_stmt(&mut self, stmts: &Vec<stmt::Stmt>) -> Result<(), InterpreterError> {
-        let tmp = self.environment.clone();
-        self.environment = Rc::new(RefCell::new(environment::Environment {
-            enclosing: Some(self.environment.clone()),
-            values: HashMap::new(),
-        }));
-        self.interpret_stmts(stmts)?;
-        self.environment = tmp;
-        Ok(())
+    pub fn block_stmt(&mut self, block: &stmt::Block) -> Result<(), Unwind> {
+        if !block.has_scope() {
+            return block.statements.iter().try_for_each(|stmt| self.stmt(stmt));
+        }
+        let environment = environment::Environment::new(self.environment.clone());
+        self.execute_block(&block.statements, gc::alloc(RefCell::new(environment)))
+    }
+
+    pub fn execute_block(
+        &mut self,
+        stmts: &[stmt::Stmt],
+        environment: Rc<RefCell<environment::Environment>>,
+    ) -> Result<(), Unwind> {
+        let previous = self.environment.replace(environment);
+        let result = stmts.iter().try_for_each(|stmt| self.stmt(stmt));
+        self.environment = previous;
+        result
     }
 
-    pub fn var_stmt(&mut self, stmt: &stmt::VarDec) -> Result<(), InterpreterError> {
+    pub fn var_stmt(&mut self, stmt: &stmt::VarDec) -> Result<(), LoxError> {
         let value = match &stmt.expression {
-            Some(expr) => Some(self.expr(&expr)?),
+            Some(expr) => self.expr(expr)?,
+            None => Value::Nil,
+        };
+        self.define(&stmt.name, value);
+        Ok(())
+    }
+
+    pub fn function_stmt(&mut self, stmt: &Rc<stmt::Function>) {
+        let function = LoxFunction {
+            declaration: stmt.clone(),
+            closure: self.environment.clone(),
+            is_initializer: false,
+        };
+        self.define(&stmt.name, Value::Function(gc::alloc(function)));
+    }
+
+    pub fn class_stmt(&mut self, stmt: &stmt::Class) -> Result<(), LoxError> {
+        let superclass = match &stmt.superclass {
+            Some(superclass) => {
+                match self.look_up_variable(&superclass.name, superclass.slot.get())? {
+                    Value::Class(class) => Some(class),
+                    _ => {
+                        return Err(LoxError::runtime(
+                            ErrorCode::SuperclassNotClass,
+                            superclass.name.span,
+                            "Superclass must be a class",
+                        ))
+                    }
+                }
+            }
             None => None,
         };
-        self.environment
-            .borrow_mut()
-            .define(stmt.name.lexeme.clone(), value);
+        // Methods of a subclass close over an extra scope that binds 'super'
+        let mut closure = self.environment.clone();
+        if let Some(superclass) = &superclass {
+            let mut environment = environment::Environment::new(closure);
+            environment.define(Value::Class(superclass.clone()));
+            closure = Some(gc::alloc(RefCell::new(environment)));
+        }
+        let mut methods = HashMap::new();
+        for method in &stmt.methods {
+            let function = LoxFunction {
+                declaration: method.clone(),
+                closure: closure.clone(),
+                is_initializer: method.name.lexeme == "init",
+            };
+            methods.insert(
+                method.name.lexeme.clone(),
+                Method::Function(gc::alloc(function)),
+            );
+        }
+        let class = LoxClass {
+            name: stmt.name.lexeme.clone(),
+            superclass,
+            methods,
+        };
+        self.define(&stmt.name, Value::Class(gc::alloc(class)));
         Ok(())
     }
 
-    pub fn print_stmt(&mut self, expr: &expr::Expr) -> Result<(), InterpreterError> {
+    /// Binds a declared name in the innermost scope: the next slot of the
+    /// local environment, or a global at the top level.
+    fn define(&mut self, name: &token_type::Token, value: Value) {
+        match &self.environment {
+            Some(environment) => environment.borrow_mut().define(value),
+            None => {
+                self.globals.insert(name.lexeme.clone(), value);
+            }
+        }
+    }
+
+    pub fn return_stmt(&mut self, stmt: &stmt::Return) -> Result<(), Unwind> {
+        let value = match &stmt.value {
+            Some(expr) => self.expr(expr)?,
+            None => Value::Nil,
+        };
+        Err(Unwind::Return(value))
+    }
+
+    pub fn print_stmt(&mut self, expr: &expr::Expr) -> Result<(), LoxError> {
         let value = self.expr(expr)?;
-        println!("{}", value);
-        Ok(())
+        writeln!(self.output, "{}", value).map_err(|e| {
+            LoxError::runtime(
+                ErrorCode::Io,
+                Span::default(),
+                format!("Failed to write output: {}", e),
+            )
+        })
     }
 
-    pub fn expr(&mut self, expr: &expr::Expr) -> Result<expr::Literal, InterpreterError> {
+    pub fn expr(&mut self, expr: &expr::Expr) -> Result<Value, LoxError> {
         match expr {
-            expr::Expr::Literal(literal) => Ok(literal.clone()),
+            expr::Expr::Literal(literal) => Ok(Value::from(&literal.value)),
             expr::Expr::Grouping(grouping) => self.expr(&grouping.expression),
+            expr::Expr::Binary(_)
+            | expr::Expr::Logical(_)
+            | expr::Expr::Call(_)
+            | expr::Expr::Get(_)
+            | expr::Expr::Index(_) => match expr.chain_left() {
+                // Most chains are a single link, which needn't be collected
+                Some(left) if left.chain_left().is_none() => {
+                    let left = self.expr(left)?;
+                    self.link(expr, left)
+                }
+                _ => {
+                    let (head, links) = expr.chain();
+                    let mut value = self.expr(head)?;
+                    for link in links {
+                        value = self.link(link, value)?;
+                    }
+                    Ok(value)
+                }
+            },
             expr::Expr::Unary(unary) => {
                 let right = self.expr(&unary.right)?;
                 match unary.operator.r#type {
                     TokenType::Minus => match right {
-                        expr::Literal::Number(x) => Ok(expr::Literal::Number(-x)),
-                        _ => Err(InterpreterError {
-                            description: "Can only negate a Number".to_string(),
-                        }),
+                        Value::Number(x) => Ok(Value::Number(-x)),
+                        _ => Err(Self::operand_error(
+                            &unary.operator,
+                            "Can only negate a Number",
+                        )),
                     },
-                    TokenType::Bang => Ok(expr::Literal::Bool(Self::is_truthy(&right))),
-                    _ => Err(InterpreterError {
-                        description: "Unrecognized unary operator".to_string(),
+                    TokenType::Bang => Ok(Value::Bool(!right.is_truthy())),
+                    _ => Err(Self::operand_error(
+                        &unary.operator,
+                        "Unrecognized unary operator",
+                    )),
+                }
+            }
+            expr::Expr::Variable(variable) => {
+                self.look_up_variable(&variable.name, variable.slot.get())
+            }
+            expr::Expr::This(this) => self.look_up_variable(&this.keyword, this.slot.get()),
+            expr::Expr::Super(super_expr) => self.super_expr(super_expr),
+            expr::Expr::Assign(assign) => {
+                let value = self.expr(&assign.value)?;
+                let assigned = match assign.slot.get() {
+                    Some(slot) => self.environment.as_ref().is_some_and(|environment| {
+                        environment
+                            .borrow_mut()
+                            .assign_at(slot.depth, slot.index, value.clone())
                     }),
+                    None => match self.globals.get_mut(&assign.name.lexeme) {
+                        Some(global) => {
+                            *global = value.clone();
+                            true
+                        }
+                        None => false,
+                    },
+                };
+                match assigned {
+                    true => Ok(value),
+                    false => Err(environment::Environment::undefined(&assign.name)),
+                }
+            }
+            expr::Expr::Set(set) => {
+                let instance = match self.expr(&set.object)? {
+                    Value::Instance(instance) => instance,
+                    _ => {
+                        return Err(LoxError::runtime(
+                            ErrorCode::NotAnInstance,
+                            set.name.span,
+                            "Only instances have fields",
+                        ))
+                    }
+                };
+                let value = self.expr(&set.value)?;
+                instance.borrow_mut().set(&set.name.lexeme, value.clone());
+                Ok(value)
+            }
+            expr::Expr::List(list) => {
+                let mut elements = vec![];
+                for element in &list.elements {
+                    elements.push(self.expr(element)?);
                 }
+                Ok(Value::list(elements))
             }
+            expr::Expr::Map(map) => {
+                let mut entries = BTreeMap::new();
+                for (key, value) in &map.entries {
+                    // Like the VM, evaluate the value before checking the key
+                    let position = self.expr(key)?;
+                    let value = self.expr(value)?;
+                    entries.insert(value::map_key(&position, key.span())?, value);
+                }
+                Ok(Value::map(entries))
+            }
+            expr::Expr::SetIndex(set_index) => {
+                let object = self.expr(&set_index.object)?;
+                let position = self.expr(&set_index.index)?;
+                let value = self.expr(&set_index.value)?;
+                let span = set_index.index.span();
+                match object {
+                    Value::List(list) => {
+                        let mut elements = list.borrow_mut();
+                        let i = value::list_index(&position, elements.len(), span)?;
+                        elements[i] = value.clone();
+                    }
+                    Value::Map(map) => {
+                        let key = value::map_key(&position, span)?;
+                        map.borrow_mut().insert(key, value.clone());
+                    }
+                    _ => return Err(Self::not_indexable(span)),
+                }
+                Ok(value)
+            }
+        }
+    }
+
+    /// Applies one link of a chain to the value of its left-hand side.
+    fn link(&mut self, link: &expr::Expr, left: Value) -> Result<Value, LoxError> {
+        match link {
             expr::Expr::Binary(binary) => {
-                let left = self.expr(&binary.left)?;
                 let right = self.expr(&binary.right)?;
                 let types = (left, right);
                 match binary.operator.r#type {
                   TokenType::Minus => {
                       match types {
-                          (expr::Literal::Number(x), expr::Literal::Number(y)) => Ok(expr::Literal::Number(x - y)),
-                          _ => Err(InterpreterError{description:"Can only subtract two Numbers".to_string()})
+                          (Value::Number(x), Value::Number(y)) => Ok(Value::Number(x - y)),
+                          _ => Err(Self::operand_error(&binary.operator, "Can only subtract two Numbers"))
                       }
                   }
                   TokenType::Slash => {
                       match types {
-                          (expr::Literal::Number(x), expr::Literal::Number(y)) => Ok(expr::Literal::Number(x / y)),
-                          _ => Err(InterpreterError{description:"Can only divide two Numbers".to_string()})
+                          (Value::Number(x), Value::Number(y)) => Ok(Value::Number(x / y)),
+                          _ => Err(Self::operand_error(&binary.operator, "Can only divide two Numbers"))
                       }
                   }
                   TokenType::Star => {
                       match types {
-                          (expr::Literal::Number(x), expr::Literal::Number(y)) => Ok(expr::Literal::Number(x * y)),
-                          _ => Err(InterpreterError{description:"Can only multiply two Numbers".to_string()})
+                          (Value::Number(x), Value::Number(y)) => Ok(Value::Number(x * y)),
+                          _ => Err(Self::operand_error(&binary.operator, "Can only multiply two Numbers"))
                       }
                   }
                   TokenType::Plus => {
                       match types {
-                          (expr::Literal::Number(x), expr::Literal::Number(y)) => Ok(expr::Literal::Number(x + y)),
-                          (expr::Literal::String(x), expr::Literal::String(y)) => Ok(expr::Literal::String(x + y.as_str())),
-                          _ => Err(InterpreterError{description:"Can only add two Numbers or two Strings".to_string()})
+                          (Value::Number(x), Value::Number(y)) => Ok(Value::Number(x + y)),
+                          (Value::String(x), Value::String(y)) => Ok(Value::String(value::concat(&x, &y))),
+                          _ => Err(Self::operand_error(&binary.operator, "Can only add two Numbers or two Strings"))
                       }
                   }
                   TokenType::Greater => {
                       match types {
-                          (expr::Literal::Number(x), expr::Literal::Number(y)) => Ok(expr::Literal::Bool(x > y)),
-                          (expr::Literal::String(x), expr::Literal::String(y)) => Ok(expr::Literal::Bool(x > y)),
-                          _ => Err(InterpreterError{description:"Can only use greater than operator on two Numbers or two Strings".to_string()})
+                          (Value::Number(x), Value::Number(y)) => Ok(Value::Bool(x > y)),
+                          (Value::String(x), Value::String(y)) => Ok(Value::Bool(x > y)),
+                          _ => Err(Self::operand_error(&binary.operator, "Can only use greater than operator on two Numbers or two Strings"))
                       }
                   }
                   TokenType::Less => {
                       match types {
-                          (expr::Literal::Number(x), expr::Literal::Number(y)) => Ok(expr::Literal::Bool(x < y)),
-                          (expr::Literal::String(x), expr::Literal::String(y)) => Ok(expr::Literal::Bool(x < y)),
-                          _ => Err(InterpreterError{description:"Can only use less than operator on two Numbers or two Strings".to_string()})
+                          (Value::Number(x), Value::Number(y)) => Ok(Value::Bool(x < y)),
+                          (Value::String(x), Value::String(y)) => Ok(Value::Bool(x < y)),
+                          _ => Err(Self::operand_error(&binary.operator, "Can only use less than operator on two Numbers or two Strings"))
                       }
                   }
                   TokenType::GreaterEqual => {
                       match types {
-                          (expr::Literal::Number(x), expr::Literal::Number(y)) => Ok(expr::Literal::Bool(x >= y)),
-                          (expr::Literal::String(x), expr::Literal::String(y)) => Ok(expr::Literal::Bool(x >= y)),
-                          _ => Err(InterpreterError{description:"Can only use greater than or equal operator on two Numbers or two Strings".to_string()})
+                          (Value::Number(x), Value::Number(y)) => Ok(Value::Bool(x >= y)),
+                          (Value::String(x), Value::String(y)) => Ok(Value::Bool(x >= y)),
+                          _ => Err(Self::operand_error(&binary.operator, "Can only use greater than or equal operator on two Numbers or two Strings"))
                       }
                   }
                   TokenType::LessEqual => {
                       match types {
-                          (expr::Literal::Number(x), expr::Literal::Number(y)) => Ok(expr::Literal::Bool(x <= y)),
-                          (expr::Literal::String(x), expr::Literal::String(y)) => Ok(expr::Literal::Bool(x <= y)),
-                          _ => Err(InterpreterError{description:"Can only use less than or equal operator on two Numbers or two Strings".to_string()})
-                      }
-                  }
-                  TokenType::BangEqual => {
-                      match types {
-                          (expr::Literal::Number(x), expr::Literal::Number(y)) => Ok(expr::Literal::Bool(x != y)),
-                          (expr::Literal::String(x), expr::Literal::String(y)) => Ok(expr::Literal::Bool(x != y)),
-                          _ => Err(InterpreterError{description:"Can only use bang equal operator on two Numbers or two Strings".to_string()})
+                          (Value::Number(x), Value::Number(y)) => Ok(Value::Bool(x <= y)),
+                          (Value::String(x), Value::String(y)) => Ok(Value::Bool(x <= y)),
+                          _ => Err(Self::operand_error(&binary.operator, "Can only use less than or equal operator on two Numbers or two Strings"))
                       }
                   }
-                  TokenType::EqualEqual => {
-                      match types {
-                          (expr::Literal::Number(x), expr::Literal::Number(y)) => Ok(expr::Literal::Bool(x == y)),
-                          (expr::Literal::String(x), expr::Literal::String(y)) => Ok(expr::Literal::Bool(x == y)),
-                          _ => Err(InterpreterError{description:"Can only use equal equal operator on two Numbers or two Strings".to_string()})
-                      }
-                  }
-                  _ => Err(InterpreterError{description:"Unrecognized binary operator".to_string()})
-                }
-            }
-            expr::Expr::Variable(variable) => {
-                match self.environment.borrow_mut().get(variable.name.clone()) {
-                    Ok(literal) => Ok(literal),
-                    Err(e) => Err(InterpreterError {
-                        description: e.description,
-                    }),
-                }
-            }
-            expr::Expr::Assign(assign) => {
-                let value = self.expr(&assign.value)?;
-                match self
-                    .environment
-                    .borrow_mut()
-                    .assign(assign.name.clone(), value.clone())
-                {
-                    Ok(_) => Ok(value),
-                    Err(e) => Err(InterpreterError {
-                        description: e.description,
-                    }),
+                  TokenType::BangEqual => Ok(Value::Bool(types.0 != types.1)),
+                  TokenType::EqualEqual => Ok(Value::Bool(types.0 == types.1)),
+                  _ => Err(Self::operand_error(&binary.operator, "Unrecognized binary operator"))
                 }
             }
             expr::Expr::Logical(logical) => {
-                let left = self.expr(&logical.left)?;
                 match logical.operator.r#type {
                     TokenType::And => {
-                        if !Self::is_truthy(&left) {
+                        if !left.is_truthy() {
                             return Ok(left);
                         }
                     }
                     TokenType::Or => {
-                        if Self::is_truthy(&left) {
+                        if left.is_truthy() {
                             return Ok(left);
                         }
                     }
                     _ => {
-                        return Err(InterpreterError {
-                            description: format!(
+                        return Err(Self::operand_error(
+                            &logical.operator,
+                            &format!(
                                 "Logical expression created with an unsupported operator: {}",
                                 logical.operator.lexeme
                             ),
-                        })
+                        ))
                     }
                 }
                 self.expr(&logical.right)
             }
+            expr::Expr::Call(call) => self.call_expr(call, left),
+            expr::Expr::Get(get) => match left {
+                Value::Instance(instance) => {
+                    LoxInstance::get(&instance, &get.name.lexeme, get.name.span)
+                }
+                _ => Err(LoxError::runtime(
+                    ErrorCode::NotAnInstance,
+                    get.name.span,
+                    "Only instances have properties",
+                )),
+            },
+            expr::Expr::Index(index) => {
+                let position = self.expr(&index.index)?;
+                let span = index.index.span();
+                match left {
+                    Value::List(list) => {
+                        let elements = list.borrow();
+                        let i = value::list_index(&position, elements.len(), span)?;
+                        Ok(elements[i].clone())
+                    }
+                    Value::Map(map) => {
+                        let key = value::map_key(&position, span)?;
+                        map.borrow().get(&key).cloned().ok_or_else(|| {
+                            LoxError::runtime(
+                                ErrorCode::UndefinedKey,
+                                span,
+                                format!("Undefined key {}", position.repr()),
+                            )
+                        })
+                    }
+                    _ => Err(Self::not_indexable(span)),
+                }
+            }
+            _ => unreachable!("Not a chain link"),
         }
     }
 
-    fn is_truthy(literal: &expr::Literal) -> bool {
-        match literal {
-            expr::Literal::Nil => false,
-            expr::Literal::Bool(b) => *b,
-            _ => true,
+    /// Like the other indexing errors, this points at the index, which is
+    /// where the VM reports it too.
+    fn not_indexable(span: Span) -> LoxError {
+        LoxError::runtime(
+            ErrorCode::NotIndexable,
+            span,
+            "Can only index lists and maps",
+        )
+    }
+
+    fn super_expr(&self, expr: &expr::Super) -> Result<Value, LoxError> {
+        // 'super' is always a local, resolved to the scope around the methods
+        let Some(slot) = expr.slot.get() else {
+            return Err(environment::Environment::undefined(&expr.keyword));
+        };
+        let superclass = match self.local(slot.depth, slot.index) {
+            Some(Value::Class(class)) => class,
+            _ => return Err(environment::Environment::undefined(&expr.keyword)),
+        };
+        // 'this' is always bound in the scope just inside the one binding 'super'
+        let instance = match self.local(slot.depth - 1, 0) {
+            Some(Value::Instance(instance)) => instance,
+            _ => {
+                return Err(LoxError::runtime(
+                    ErrorCode::UndefinedVariable,
+                    expr.keyword.span,
+                    "Undefined variable this",
+                ))
+            }
+        };
+        match superclass.find_method(&expr.method.lexeme) {
+            Some(method) => Ok(method.bind(instance)),
+            None => Err(LoxError::runtime(
+                ErrorCode::UndefinedProperty,
+                expr.method.span,
+                format!("Undefined property '{}'", expr.method.lexeme),
+            )),
         }
     }
+
+    fn look_up_variable(
+        &self,
+        name: &token_type::Token,
+        slot: Option<Slot>,
+    ) -> Result<Value, LoxError> {
+        let value = match slot {
+            Some(slot) => self.local(slot.depth, slot.index),
+            None => self.globals.get(&name.lexeme).cloned(),
+        };
+        value.ok_or_else(|| environment::Environment::undefined(name))
+    }
+
+    fn local(&self, depth: usize, index: usize) -> Option<Value> {
+        self.environment
+            .as_ref()
+            .and_then(|environment| environment.borrow().get_at(depth, index))
+    }
+
+    fn call_expr(&mut self, call: &expr::Call, callee: Value) -> Result<Value, LoxError> {
+        let mut arguments = vec![];
+        for argument in &call.arguments {
+            arguments.push(self.expr(argument)?);
+        }
+        let callable: &dyn Callable = match &callee {
+            Value::Function(function) => function.as_ref(),
+            Value::Class(class) => class,
+            Value::Native(native) => {
+                Self::check_arity(native.arity, arguments.len(), &call.paren)?;
+                return native.call(&arguments, call.paren.span);
+            }
+            _ => {
+                return Err(LoxError::runtime(
+                    ErrorCode::NotCallable,
+                    call.paren.span,
+                    "Can only call functions and classes",
+                ))
+            }
+        };
+        Self::check_arity(callable.arity(), arguments.len(), &call.paren)?;
+        if self.call_depth == MAX_CALL_DEPTH {
+            return Err(LoxError::runtime(
+                ErrorCode::StackOverflow,
+                call.paren.span,
+                "Stack overflow",
+            ));
+        }
+        self.call_depth += 1;
+        let result = callable.call(self, arguments);
+        self.call_depth -= 1;
+        result
+    }
+
+    fn check_arity(arity: usize, count: usize, paren: &token_type::Token) -> Result<(), LoxError> {
+        if count != arity {
+            return Err(LoxError::runtime(
+                ErrorCode::ArityMismatch,
+                paren.span,
+                format!("Expected {} arguments but got {}", arity, count),
+            ));
+        }
+        Ok(())
+    }
+
+    fn operand_error(operator: &token_type::Token, message: &str) -> LoxError {
+        LoxError::runtime(ErrorCode::InvalidOperand, operator.span, message)
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-#[derive(Debug)]
-pub struct InterpreterError {
-    description: String,
+/// Non-local exits out of statement execution. A `return` unwinds through
+/// any enclosing blocks until it reaches the function call that catches it;
+/// `break` and `continue` stop at the innermost loop.
+pub enum Unwind {
+    Error(LoxError),
+    Return(Value),
+    Break(Span),
+    Continue(Span),
+}
+impl Unwind {
+    /// Converts an exit that reached a boundary it can't cross, such as the
+    /// top level, into an error. The resolver normally rules these out.
+    pub fn into_error(self) -> LoxError {
+        match self {
+            Unwind::Error(error) => error,
+            Unwind::Return(_) => LoxError::runtime(
+                ErrorCode::TopLevelReturn,
+                Span::default(),
+                "Can't return from top-level code",
+            ),
+            Unwind::Break(span) => LoxError::runtime(
+                ErrorCode::BreakOutsideLoop,
+                span,
+                "Can't use 'break' outside of a loop",
+            ),
+            Unwind::Continue(span) => LoxError::runtime(
+                ErrorCode::ContinueOutsideLoop,
+                span,
+                "Can't use 'continue' outside of a loop",
+            ),
+        }
+    }
 }
-impl Error for InterpreterError {}
-impl fmt::Display for InterpreterError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "InterpreterError: {}", self.description)
+impl From<LoxError> for Unwind {
+    fn from(error: LoxError) -> Self {
+        Unwind::Error(error)
     }
 }
@@ -0,0 +1,48 @@
+//! An interpreter for Lox, the language from *Crafting Interpreters*, with
+//! both a tree-walking backend and a bytecode VM.
+//!
+//! ```
+//! let mut lox = rlox::Lox::new();
+//! lox.eval("fun square(x) { return x * x; }").unwrap();
+//! let value = lox.eval("square(4);").unwrap();
+//! assert_eq!(value, rlox::Value::Number(16.0));
+//! ```
+pub mod callable;
+pub mod chunk;
+pub mod class;
+pub mod compiler;
+pub mod diagnostic;
+pub mod disassembler;
+pub mod environment;
+pub mod error;
+pub mod expr;
+pub mod gc;
+pub mod interpreter;
+pub mod lox;
+pub mod loxc;
+pub mod native;
+pub mod output;
+pub mod parser;
+pub mod resolver;
+pub mod scanner;
+pub mod stmt;
+pub mod symbol;
+pub mod token_type;
+pub mod value;
+pub mod vm;
+
+pub use diagnostic::{ColorChoice, Diagnostic};
+pub use disassembler::disassemble;
+pub use error::{ErrorCode, LoxError, Span};
+pub use interpreter::Interpreter;
+pub use lox::{compile, Backend, Lox};
+pub use parser::Parser;
+pub use scanner::Scanner;
+pub use symbol::Symbol;
+pub use value::Value;
+pub use vm::Vm;
+
+/// Runs the examples in the README as doc tests.
+#[cfg(doctest)]
+#[doc = include_str!("../README.md")]
+pub struct ReadmeDoctests;
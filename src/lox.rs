@@ -1,79 +1,90 @@
+use crate::chunk::FunctionProto;
+use crate::compiler::Compiler;
+use crate::error::LoxError;
 use crate::interpreter::Interpreter;
-use crate::parser::*;
+use crate::parser::Parser;
+use crate::resolver::Resolver;
 use crate::scanner::Scanner;
-use clap::{App, Arg};
-use std::fs;
-use std::io;
-use std::process;
+use crate::stmt::Stmt;
+use crate::value::Value;
+use crate::vm::Vm;
+use std::rc::Rc;
 
+/// Which engine runs scripts. Both accept the same programs and produce the
+/// same output and errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    #[default]
+    TreeWalk,
+    Vm,
+}
+
+/// Runs Lox source against a persistent interpreter, so globals defined by one
+/// call to `eval` are visible to the next.
 pub struct Lox {
     pub interpreter: Interpreter,
-    pub had_error: bool,
+    pub vm: Vm,
+    backend: Backend,
 }
 impl Lox {
-    pub fn main(&mut self) {
-        let args = App::new("rlox")
-            .arg(Arg::with_name("filepath").takes_value(true))
-            .get_matches();
-
-        match args.value_of("filepath") {
-            Some(filepath) => {
-                self.run_file(filepath);
-            }
-            None => {
-                self.run_prompt();
-            }
-        }
+    pub fn new() -> Self {
+        Self::with_backend(Backend::TreeWalk)
     }
 
-    fn run_file(&mut self, filepath: &str) {
-        let contents = fs::read_to_string(filepath).expect(&format!(
-            "Failed to read from given filepath: {:?}",
-            filepath
-        ));
-        Lox::run(self, contents);
-        if self.had_error {
-            process::exit(65);
+    pub fn with_backend(backend: Backend) -> Self {
+        Lox {
+            interpreter: Interpreter::new(),
+            vm: Vm::new(),
+            backend,
         }
     }
 
-    fn run_prompt(&mut self) {
-        let lines = io::stdin().lines();
-        for line in lines {
-            Lox::run(self, line.unwrap());
-            self.had_error = false;
-        }
+    /// Scans, parses, resolves and runs `source` on the selected backend.
+    /// Returns the value of the final statement if it is an expression
+    /// statement, otherwise nil. Every error found in the first failing phase
+    /// is returned.
+    pub fn eval(&mut self, source: &str) -> Result<Value, Vec<LoxError>> {
+        Ok(self.eval_repl(source, 0)?.unwrap_or(Value::Nil))
     }
 
-    fn run(&mut self, source: String) {
-        let tokens = match Scanner::scan_tokens(source) {
-            Ok(tokens) => tokens,
-            Err(errors) => {
-                self.had_error = true;
-                for error in errors {
-                    Self::report(error, "");
-                }
-                return;
+    /// Like `eval`, but runs only the part of `session` from byte `from` on,
+    /// which must start a line, and returns `None` unless the final statement
+    /// is an expression statement, so a REPL can echo `nil;` but not `var x;`.
+    /// Spans point into the whole `session`, so a REPL that passes everything
+    /// entered so far can show errors from functions defined earlier.
+    pub fn eval_repl(
+        &mut self,
+        session: &str,
+        from: usize,
+    ) -> Result<Option<Value>, Vec<LoxError>> {
+        let tokens = Scanner::scan_tokens_from(session, from)?;
+        let stmts = Parser::parse(tokens)?;
+        Resolver::resolve(&stmts)?;
+        let is_expression = matches!(stmts.last(), Some(Stmt::Expr(_)));
+        let value = match self.backend {
+            Backend::TreeWalk => self
+                .interpreter
+                .interpret_stmts(&stmts)
+                .map_err(|e| vec![e]),
+            Backend::Vm => {
+                let script = Compiler::compile(&stmts)?;
+                self.vm.interpret(script).map_err(|e| vec![e])
             }
-        };
-        match Parser::parse(tokens) {
-            Ok(stmts) => match self.interpreter.interpret_stmts(&stmts) {
-                Ok(_) => {}
-                Err(e) => println!("{}", e),
-            },
-            Err(e) => println!("{}", e),
-        }
+        }?;
+        Ok(is_expression.then_some(value))
     }
-
-    fn report(error: Error, _where: &str) {
-        println!(
-            "[line {0}] Error{1}: {2}",
-            error.line, _where, error.message
-        );
+}
+impl Default for Lox {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-pub struct Error {
-    pub line: usize,
-    pub message: String,
+/// Scans, parses, resolves and compiles `source` to bytecode for the VM,
+/// without running it.
+pub fn compile(source: &str) -> Result<Rc<FunctionProto>, Vec<LoxError>> {
+    let tokens = Scanner::scan_tokens(source.to_string())?;
+    let stmts = Parser::parse(tokens)?;
+    Resolver::resolve(&stmts)?;
+    Compiler::compile(&stmts)
 }
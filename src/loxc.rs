@@ -0,0 +1,486 @@
+//! The `.loxc` format: a compiled script saved to disk so it can be run
+//! without scanning, parsing or compiling it again.
+//!
+//! A file is the magic bytes `LOXC`, a little-endian u16 format version, then
+//! the script's function prototype. A prototype is its name, arity, upvalue
+//! count, code, line table and constants, where a constant may itself be a
+//! nested prototype. Integers are little-endian u32 unless noted, strings
+//! are a u32 length followed by UTF-8. The line table stores each run of
+//! bytes compiled from the same span once.
+
+use crate::chunk::{Chunk, Constant, FunctionProto, OpCode};
+use crate::error::{ErrorCode, LoxError, Span};
+use crate::symbol::Symbol;
+use std::rc::Rc;
+
+pub const MAGIC: &[u8; 4] = b"LOXC";
+
+/// Bumped whenever the encoding or the instruction set changes. Files with
+/// any other version are rejected rather than misread.
+pub const VERSION: u16 = 1;
+
+/// Functions nest at most as deep as the parser allows, so deeper nesting
+/// means the file is corrupt.
+const MAX_NESTING: usize = 256;
+
+const NUMBER: u8 = 0;
+const STRING: u8 = 1;
+const FUNCTION: u8 = 2;
+
+/// Whether `bytes` start like a `.loxc` file rather than Lox source.
+pub fn is_loxc(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+pub fn encode(script: &FunctionProto) -> Vec<u8> {
+    let mut out = MAGIC.to_vec();
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    encode_function(&mut out, script);
+    out
+}
+
+fn encode_function(out: &mut Vec<u8>, function: &FunctionProto) {
+    write_str(out, &function.name);
+    write_u32(out, function.arity as u32);
+    write_u32(out, function.upvalue_count as u32);
+
+    let chunk = &function.chunk;
+    write_u32(out, chunk.code.len() as u32);
+    out.extend_from_slice(&chunk.code);
+
+    let mut runs: Vec<(u32, Span)> = vec![];
+    for span in &chunk.spans {
+        match runs.last_mut() {
+            Some((length, last)) if last == span => *length += 1,
+            _ => runs.push((1, *span)),
+        }
+    }
+    write_u32(out, runs.len() as u32);
+    for (length, span) in runs {
+        write_u32(out, length);
+        for field in [span.line, span.column, span.start, span.end] {
+            write_u32(out, field as u32);
+        }
+    }
+
+    write_u32(out, chunk.constants.len() as u32);
+    for constant in &chunk.constants {
+        match constant {
+            Constant::Number(x) => {
+                out.push(NUMBER);
+                out.extend_from_slice(&x.to_le_bytes());
+            }
+            Constant::String(x) => {
+                out.push(STRING);
+                write_str(out, x);
+            }
+            Constant::Function(nested) => {
+                out.push(FUNCTION);
+                encode_function(out, nested);
+            }
+        }
+    }
+}
+
+fn write_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_str(out: &mut Vec<u8>, value: &str) {
+    write_u32(out, value.len() as u32);
+    out.extend_from_slice(value.as_bytes());
+}
+
+/// Reads a script from `.loxc` bytes. Besides the encoding, the bytecode is
+/// checked the way the compiler would have produced it: valid opcodes and
+/// operands, constants of the right kind, jumps landing on instructions, a
+/// consistent stack depth on every path and captured variables closed before
+/// they leave the stack. Anything else is rejected, so a corrupt file can't
+/// crash the VM.
+pub fn decode(bytes: &[u8]) -> Result<Rc<FunctionProto>, LoxError> {
+    let mut reader = Reader { bytes, position: 0 };
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err(invalid("Not a .loxc file"));
+    }
+    let version = u16::from_le_bytes([reader.u8()?, reader.u8()?]);
+    if version != VERSION {
+        return Err(invalid(&format!(
+            "Unsupported .loxc version {} (expected {})",
+            version, VERSION
+        )));
+    }
+    let script = reader.function(0)?;
+    if reader.position != bytes.len() {
+        return Err(invalid("Unexpected data after the script"));
+    }
+    // The VM runs the script with nothing but itself on the stack
+    if script.arity != 0 || script.upvalue_count != 0 {
+        return Err(invalid("The script can't take parameters or upvalues"));
+    }
+    Ok(Rc::new(script))
+}
+
+fn invalid(message: &str) -> LoxError {
+    LoxError::compile(
+        ErrorCode::InvalidBytecode,
+        Span::default(),
+        format!("Invalid bytecode: {}", message),
+    )
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+impl Reader<'_> {
+    fn take(&mut self, length: usize) -> Result<&[u8], LoxError> {
+        let end = self
+            .position
+            .checked_add(length)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| invalid("Unexpected end of file"))?;
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, LoxError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, LoxError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn usize(&mut self) -> Result<usize, LoxError> {
+        Ok(self.u32()? as usize)
+    }
+
+    /// Reads an element count. Every element takes at least one byte, so a
+    /// count larger than what is left is corrupt and nothing is allocated
+    /// for it.
+    fn count(&mut self) -> Result<usize, LoxError> {
+        let count = self.usize()?;
+        match count <= self.bytes.len() - self.position {
+            true => Ok(count),
+            false => Err(invalid("Count exceeds the file size")),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, LoxError> {
+        let length = self.count()?;
+        let bytes = self.take(length)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| invalid("String is not UTF-8"))
+    }
+
+    fn function(&mut self, depth: usize) -> Result<FunctionProto, LoxError> {
+        if depth > MAX_NESTING {
+            return Err(invalid("Functions nested too deeply"));
+        }
+        let name = self.string()?;
+        let arity = self.usize()?;
+        let upvalue_count = self.usize()?;
+
+        let length = self.count()?;
+        let code = self.take(length)?.to_vec();
+        let mut spans = Vec::with_capacity(code.len());
+        for _ in 0..self.count()? {
+            let run = self.usize()?;
+            let span = Span {
+                line: self.usize()?,
+                column: self.usize()?,
+                start: self.usize()?,
+                end: self.usize()?,
+            };
+            if run > code.len() - spans.len() {
+                return Err(invalid("Line table is longer than the code"));
+            }
+            spans.resize(spans.len() + run, span);
+        }
+        if spans.len() != code.len() {
+            return Err(invalid("Line table is shorter than the code"));
+        }
+
+        let mut constants = vec![];
+        for _ in 0..self.count()? {
+            let constant = match self.u8()? {
+                NUMBER => {
+                    let bytes = self.take(8)?;
+                    let mut number = [0; 8];
+                    number.copy_from_slice(bytes);
+                    Constant::Number(f64::from_le_bytes(number))
+                }
+                STRING => Constant::String(Symbol::intern(&self.string()?)),
+                FUNCTION => Constant::Function(Rc::new(self.function(depth + 1)?)),
+                tag => return Err(invalid(&format!("Unknown constant tag {}", tag))),
+            };
+            constants.push(constant);
+        }
+        let function = FunctionProto {
+            name,
+            arity,
+            upvalue_count,
+            chunk: Chunk {
+                code,
+                constants,
+                spans,
+            },
+        };
+        // Nested functions were verified as they were read, so each one is
+        // checked once however many closures refer to it
+        verify(&function)?;
+        Ok(function)
+    }
+}
+
+/// Checks `function` can run without the VM reading outside its code,
+/// constants, upvalues or stack. Its nested functions are checked separately.
+fn verify(function: &FunctionProto) -> Result<(), LoxError> {
+    let error = |offset: usize, message: &str| {
+        invalid(&format!("{} at offset {} in {}", message, offset, function))
+    };
+    if function.arity > 255 || function.upvalue_count > 256 {
+        return Err(error(0, "Too many parameters or upvalues"));
+    }
+    let chunk = &function.chunk;
+    let code = &chunk.code;
+    let byte = |offset: usize| {
+        code.get(offset)
+            .copied()
+            .ok_or_else(|| error(offset, "Truncated instruction"))
+    };
+    let short =
+        |offset: usize| Ok::<_, LoxError>(u16::from_be_bytes([byte(offset)?, byte(offset + 1)?]));
+    let constant = |offset: usize| {
+        let index = short(offset)?;
+        chunk
+            .constants
+            .get(index as usize)
+            .ok_or_else(|| error(offset, "Constant index out of range"))
+    };
+    let name = |offset: usize| match constant(offset)? {
+        Constant::String(_) => Ok(()),
+        _ => Err(error(offset, "Expected a string constant")),
+    };
+
+    // The code has to split into whole instructions, since the disassembler
+    // walks it linearly, including any unreachable parts.
+    let mut starts = vec![false; code.len()];
+    let mut offset = 0;
+    while offset < code.len() {
+        starts[offset] = true;
+        let op = OpCode::try_from(code[offset])
+            .map_err(|byte| error(offset, &format!("Unknown opcode {}", byte)))?;
+        let length = match op {
+            OpCode::GetLocal
+            | OpCode::SetLocal
+            | OpCode::GetUpvalue
+            | OpCode::SetUpvalue
+            | OpCode::Call => 2,
+            OpCode::Constant
+            | OpCode::GetGlobal
+            | OpCode::DefineGlobal
+            | OpCode::SetGlobal
+            | OpCode::GetProperty
+            | OpCode::SetProperty
+            | OpCode::GetSuper
+            | OpCode::Jump
+            | OpCode::JumpIfFalse
+            | OpCode::Loop
+            | OpCode::List => 3,
+            OpCode::Closure => match constant(offset + 1)? {
+                Constant::Function(nested) => 3 + 2 * nested.upvalue_count,
+                _ => 3,
+            },
+            OpCode::Class => 6 + 2 * short(offset + 4)? as usize,
+            _ => 1,
+        };
+        if offset + length > code.len() {
+            return Err(error(offset, "Truncated instruction"));
+        }
+        offset += length;
+    }
+
+    // Stack depth relative to the frame before each instruction, and the
+    // slots closures may have captured by then, found by following every path
+    // from the entry. Slot 0 and the parameters are already on the stack.
+    let mut states: Vec<Option<(usize, Vec<usize>)>> = vec![None; code.len()];
+    let mut pending = vec![(0, function.arity + 1, vec![])];
+    while let Some((offset, depth, mut captured)) = pending.pop() {
+        if offset >= code.len() {
+            return Err(error(offset, "Execution runs past the end of the code"));
+        }
+        if !starts[offset] {
+            return Err(error(offset, "Jump into the middle of an instruction"));
+        }
+        match &mut states[offset] {
+            Some((seen, _)) if *seen != depth => {
+                return Err(error(offset, "Inconsistent stack depth"))
+            }
+            // A slot captured on any path here may be captured, so the paths
+            // are followed again until that stops growing
+            Some((_, seen)) => {
+                if captured.iter().all(|slot| seen.contains(slot)) {
+                    continue;
+                }
+                seen.extend(captured);
+                seen.sort_unstable();
+                seen.dedup();
+                captured = seen.clone();
+            }
+            None => states[offset] = Some((depth, captured.clone())),
+        }
+        let op = OpCode::try_from(code[offset])
+            .map_err(|byte| error(offset, &format!("Unknown opcode {}", byte)))?;
+        // (values popped, values pushed, length of the instruction)
+        let (pops, pushes, length) = match op {
+            OpCode::Constant => match constant(offset + 1)? {
+                // Functions are only loaded by `Closure`
+                Constant::Function(_) => {
+                    return Err(error(offset, "Expected a number or string constant"));
+                }
+                _ => (0, 1, 3),
+            },
+            OpCode::Nil | OpCode::True | OpCode::False | OpCode::Map => (0, 1, 1),
+            OpCode::Pop | OpCode::Print | OpCode::CloseUpvalue => (1, 0, 1),
+            OpCode::GetLocal | OpCode::SetLocal => {
+                if byte(offset + 1)? as usize >= depth {
+                    return Err(error(offset, "Local slot out of range"));
+                }
+                match op {
+                    OpCode::GetLocal => (0, 1, 2),
+                    _ => (1, 1, 2),
+                }
+            }
+            OpCode::GetUpvalue | OpCode::SetUpvalue => {
+                if byte(offset + 1)? as usize >= function.upvalue_count {
+                    return Err(error(offset, "Upvalue index out of range"));
+                }
+                match op {
+                    OpCode::GetUpvalue => (0, 1, 2),
+                    _ => (1, 1, 2),
+                }
+            }
+            OpCode::GetGlobal | OpCode::DefineGlobal | OpCode::SetGlobal => {
+                name(offset + 1)?;
+                match op {
+                    OpCode::GetGlobal => (0, 1, 3),
+                    OpCode::DefineGlobal => (1, 0, 3),
+                    _ => (1, 1, 3),
+                }
+            }
+            OpCode::GetProperty => {
+                name(offset + 1)?;
+                (1, 1, 3)
+            }
+            OpCode::SetProperty | OpCode::GetSuper => {
+                name(offset + 1)?;
+                (2, 1, 3)
+            }
+            OpCode::Equal
+            | OpCode::NotEqual
+            | OpCode::Greater
+            | OpCode::GreaterEqual
+            | OpCode::Less
+            | OpCode::LessEqual
+            | OpCode::Add
+            | OpCode::Subtract
+            | OpCode::Multiply
+            | OpCode::Divide
+            | OpCode::GetIndex => (2, 1, 1),
+            OpCode::Not | OpCode::Negate => (1, 1, 1),
+            OpCode::MapEntry => (3, 1, 1),
+            OpCode::SetIndex => (3, 1, 1),
+            OpCode::Jump | OpCode::JumpIfFalse | OpCode::Loop => {
+                let distance = short(offset + 1)? as usize;
+                let target = match op {
+                    OpCode::Loop => (offset + 3).checked_sub(distance),
+                    _ => Some(offset + 3 + distance),
+                };
+                let target = target.ok_or_else(|| error(offset, "Jump out of range"))?;
+                let needed = (op == OpCode::JumpIfFalse) as usize;
+                if depth < 1 + needed {
+                    return Err(error(offset, "Stack underflow"));
+                }
+                pending.push((target, depth, captured.clone()));
+                if op != OpCode::JumpIfFalse {
+                    continue;
+                }
+                (1, 1, 3)
+            }
+            OpCode::Call => (byte(offset + 1)? as usize + 1, 1, 2),
+            OpCode::Closure => {
+                let Constant::Function(nested) = constant(offset + 1)? else {
+                    return Err(error(offset, "Expected a function constant"));
+                };
+                for i in 0..nested.upvalue_count {
+                    let index = byte(offset + 4 + 2 * i)? as usize;
+                    let in_range = match byte(offset + 3 + 2 * i)? {
+                        1 => index < depth,
+                        0 => index < function.upvalue_count,
+                        _ => false,
+                    };
+                    if !in_range {
+                        return Err(error(offset, "Captured variable out of range"));
+                    }
+                    if byte(offset + 3 + 2 * i)? == 1 && !captured.contains(&index) {
+                        captured.push(index);
+                    }
+                }
+                (0, 1, 3 + 2 * nested.upvalue_count)
+            }
+            OpCode::Return => {
+                if depth < 2 {
+                    return Err(error(offset, "Stack underflow"));
+                }
+                // The result is popped before the frame's upvalues are closed
+                if captured.contains(&(depth - 1)) {
+                    return Err(error(
+                        offset,
+                        "Captured variable discarded without closing it",
+                    ));
+                }
+                continue;
+            }
+            OpCode::Class => {
+                name(offset + 1)?;
+                let superclass = byte(offset + 3)? as usize;
+                if superclass > 1 {
+                    return Err(error(offset, "Invalid superclass flag"));
+                }
+                let count = short(offset + 4)? as usize;
+                for i in 0..count {
+                    name(offset + 6 + 2 * i)?;
+                }
+                // The superclass stays below the methods
+                if depth < count + superclass + 1 {
+                    return Err(error(offset, "Stack underflow"));
+                }
+                (count, 1, 6 + 2 * count)
+            }
+            OpCode::List => (short(offset + 1)? as usize, 1, 3),
+        };
+        // Slot 0 is never popped
+        if depth < pops + 1 {
+            return Err(error(offset, "Stack underflow"));
+        }
+        // Only `CloseUpvalue` may discard a captured variable, or its
+        // upvalue would be left pointing past the top of the stack
+        match op {
+            OpCode::CloseUpvalue => captured.retain(|&slot| slot != depth - 1),
+            _ if captured.iter().any(|&slot| slot >= depth - pops) => {
+                return Err(error(
+                    offset,
+                    "Captured variable discarded without closing it",
+                ));
+            }
+            _ => {}
+        }
+        if offset + length > code.len() {
+            return Err(error(offset, "Truncated instruction"));
+        }
+        pending.push((offset + length, depth - pops + pushes, captured));
+    }
+    Ok(())
+}
@@ -1,22 +1,186 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
-mod environment;
-mod expr;
-mod interpreter;
-mod lox;
-mod parser;
-mod scanner;
-mod stmt;
-mod token_type;
+use clap::{App, Arg, SubCommand};
+use rlox::{loxc, Backend, ColorChoice, Diagnostic, Lox, LoxError, Value};
+use std::path::Path;
+use std::{fs, io, process};
+
+// Exit codes from sysexits.h for problems with the files themselves
+const EX_DATAERR: i32 = 65;
+const EX_NOINPUT: i32 = 66;
+const EX_IOERR: i32 = 74;
 
 fn main() {
-    let mut lox = lox::Lox {
-        had_error: false,
-        interpreter: interpreter::Interpreter {
-            environment: Rc::new(RefCell::new(environment::Environment {
-                values: HashMap::new(),
-                enclosing: None,
-            })),
-        },
+    let args = App::new("rlox")
+        .arg(Arg::with_name("filepath").takes_value(true))
+        .arg(
+            Arg::with_name("color")
+                .long("color")
+                .takes_value(true)
+                .value_name("WHEN")
+                .possible_values(&["auto", "always", "never"])
+                .default_value("auto")
+                .global(true)
+                .help("Whether to color error output"),
+        )
+        .arg(
+            Arg::with_name("vm")
+                .long("vm")
+                .help("Run on the bytecode VM instead of the tree-walker"),
+        )
+        .arg(
+            Arg::with_name("dump-bytecode")
+                .long("dump-bytecode")
+                .help("Print the compiled bytecode instead of running"),
+        )
+        .arg(
+            Arg::with_name("gc-stress")
+                .long("gc-stress")
+                .help("Collect garbage on every allocation, to debug the collector"),
+        )
+        .subcommand(
+            SubCommand::with_name("compile")
+                .about("Compiles a script to a .loxc file without running it")
+                .arg(Arg::with_name("input").required(true))
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .help(
+                            "Where to write the bytecode [default: input with a .loxc extension]",
+                        ),
+                ),
+        )
+        .get_matches();
+
+    let color = args
+        .value_of("color")
+        .and_then(|when| when.parse::<ColorChoice>().ok())
+        .unwrap_or(ColorChoice::Auto)
+        .enabled();
+    if let Some(args) = args.subcommand_matches("compile") {
+        let input = args.value_of("input").unwrap_or_default();
+        let output = match args.value_of("output") {
+            Some(output) => Path::new(output).to_path_buf(),
+            None => Path::new(input).with_extension("loxc"),
+        };
+        return compile_file(input, &output, color);
+    }
+    if args.is_present("gc-stress") {
+        rlox::gc::set_stress(true);
+    }
+    let backend = match args.is_present("vm") {
+        true => Backend::Vm,
+        false => Backend::TreeWalk,
     };
-    lox.main();
+    let mut lox = Lox::with_backend(backend);
+    let dump = args.is_present("dump-bytecode");
+    match args.value_of("filepath") {
+        Some(filepath) => run_file(&mut lox, filepath, color, dump),
+        None => run_prompt(&mut lox, color, dump),
+    }
+}
+
+fn run_file(lox: &mut Lox, filepath: &str, color: bool, dump: bool) {
+    let bytes = read_file(filepath);
+    if loxc::is_loxc(&bytes) {
+        return run_bytecode(lox, &bytes, filepath, color, dump);
+    }
+    let contents = utf8(bytes, filepath);
+    if let Err(errors) = run(lox, &contents, 0, dump) {
+        process::exit(report(&errors, &contents, filepath, color));
+    }
+}
+
+/// Runs a precompiled script. Bytecode only runs on the VM, whichever
+/// backend was asked for.
+fn run_bytecode(lox: &mut Lox, bytes: &[u8], filepath: &str, color: bool, dump: bool) {
+    let result = loxc::decode(bytes).and_then(|script| match dump {
+        true => {
+            print!("{}", rlox::disassemble(&script));
+            Ok(())
+        }
+        false => lox.vm.interpret(script).map(|_| ()),
+    });
+    // The source isn't available, so errors only show their location
+    if let Err(error) = result {
+        process::exit(report(&[error], "", filepath, color));
+    }
+}
+
+fn compile_file(input: &str, output: &Path, color: bool) {
+    let contents = utf8(read_file(input), input);
+    match rlox::compile(&contents) {
+        Ok(script) => fs::write(output, loxc::encode(&script)).unwrap_or_else(|e| {
+            fail(
+                &format!("Can't write to {}: {}", output.display(), e),
+                EX_IOERR,
+            )
+        }),
+        Err(errors) => process::exit(report(&errors, &contents, input, color)),
+    }
+}
+
+fn read_file(path: &str) -> Vec<u8> {
+    fs::read(path).unwrap_or_else(|e| fail(&format!("Can't read {}: {}", path, e), EX_NOINPUT))
+}
+
+fn utf8(bytes: Vec<u8>, path: &str) -> String {
+    String::from_utf8(bytes)
+        .unwrap_or_else(|_| fail(&format!("{} isn't valid UTF-8", path), EX_DATAERR))
+}
+
+/// Reports a problem with a file itself and exits with `code`.
+fn fail(message: &str, code: i32) -> ! {
+    eprintln!("error: {}", message);
+    process::exit(code)
+}
+
+fn run_prompt(lox: &mut Lox, color: bool, dump: bool) {
+    // Everything entered so far, which errors are rendered against: a call
+    // can fail in a function defined on an earlier line
+    let mut session = String::new();
+    for line in io::stdin().lines() {
+        // Dumping runs nothing, so only the current line matters
+        if dump {
+            session.clear();
+        }
+        if !session.is_empty() {
+            session.push('\n');
+        }
+        let from = session.len();
+        session.push_str(&line.unwrap());
+        match run(lox, &session, from, dump) {
+            Ok(Some(value)) => println!("{}", value.repr()),
+            Ok(None) => {}
+            Err(errors) => {
+                report(&errors, &session, "<stdin>", color);
+            }
+        }
+    }
+}
+
+/// Runs `source` from byte `from` on and returns the value of a trailing
+/// expression statement, or with `dump` prints the bytecode all of `source`
+/// compiles to instead.
+fn run(
+    lox: &mut Lox,
+    source: &str,
+    from: usize,
+    dump: bool,
+) -> Result<Option<Value>, Vec<LoxError>> {
+    match dump {
+        true => {
+            print!("{}", rlox::disassemble(&*rlox::compile(source)?));
+            Ok(None)
+        }
+        false => lox.eval_repl(source, from),
+    }
+}
+
+/// Prints `errors` to stderr and returns the exit code they map to.
+fn report(errors: &[LoxError], source: &str, path: &str, color: bool) -> i32 {
+    for error in errors {
+        eprintln!("{}", Diagnostic::from(error).render(source, path, color));
+    }
+    errors.iter().map(LoxError::exit_code).max().unwrap_or(0)
 }
@@ -0,0 +1,94 @@
+use crate::error::{ErrorCode, LoxError, Span};
+use crate::value::{MapKey, Value};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub type NativeFn = dyn Fn(&[Value]) -> Result<Value, String>;
+
+/// A Lox callable implemented in Rust. Arguments are checked against `arity`
+/// before `function` runs; an `Err` becomes a runtime error in the script.
+pub struct NativeFunction {
+    pub name: String,
+    pub arity: usize,
+    pub function: Box<NativeFn>,
+}
+impl NativeFunction {
+    /// Runs the function, reporting a failure at `span`, the call site.
+    pub fn call(&self, arguments: &[Value], span: Span) -> Result<Value, LoxError> {
+        (self.function)(arguments).map_err(|message| {
+            LoxError::runtime(
+                ErrorCode::NativeError,
+                span,
+                format!("{}: {}", self.name, message),
+            )
+        })
+    }
+}
+
+type Builtin = fn(&[Value]) -> Result<Value, String>;
+
+/// The natives every backend defines as globals: name, arity and function.
+pub const BUILTINS: [(&str, usize, Builtin); 6] = [
+    ("clock", 0, clock),
+    ("len", 1, len),
+    ("keys", 1, keys),
+    ("values", 1, values),
+    ("has", 2, has),
+    ("remove", 2, remove),
+];
+
+/// Seconds since the Unix epoch, for timing scripts.
+pub fn clock(_arguments: &[Value]) -> Result<Value, String> {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(elapsed) => Ok(Value::Number(elapsed.as_secs_f64())),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// The number of elements in a list or map, or of characters in a string.
+pub fn len(arguments: &[Value]) -> Result<Value, String> {
+    match arguments {
+        [Value::List(list)] => Ok(Value::Number(list.borrow().len() as f64)),
+        [Value::Map(map)] => Ok(Value::Number(map.borrow().len() as f64)),
+        [Value::String(string)] => Ok(Value::Number(string.chars().count() as f64)),
+        _ => Err("Expected a list, a map or a string".to_string()),
+    }
+}
+
+/// A list of a map's keys, in iteration order.
+pub fn keys(arguments: &[Value]) -> Result<Value, String> {
+    match arguments {
+        [Value::Map(map)] => Ok(Value::list(
+            map.borrow().keys().cloned().map(Value::from).collect(),
+        )),
+        _ => Err("Expected a map".to_string()),
+    }
+}
+
+/// A list of a map's values, in the same order as `keys`.
+pub fn values(arguments: &[Value]) -> Result<Value, String> {
+    match arguments {
+        [Value::Map(map)] => Ok(Value::list(map.borrow().values().cloned().collect())),
+        _ => Err("Expected a map".to_string()),
+    }
+}
+
+/// Whether a map contains a key.
+pub fn has(arguments: &[Value]) -> Result<Value, String> {
+    match arguments {
+        [Value::Map(map), key] => Ok(Value::Bool(
+            map.borrow().contains_key(&MapKey::try_from(key)?),
+        )),
+        _ => Err("Expected a map and a key".to_string()),
+    }
+}
+
+/// Removes a key from a map, returning its value, or nil if it was absent.
+pub fn remove(arguments: &[Value]) -> Result<Value, String> {
+    match arguments {
+        [Value::Map(map), key] => Ok(map
+            .borrow_mut()
+            .remove(&MapKey::try_from(key)?)
+            .unwrap_or(Value::Nil)),
+        _ => Err("Expected a map and a key".to_string()),
+    }
+}
@@ -1,86 +1,302 @@
+use crate::error::{ErrorCode, LoxError, Span};
 use crate::expr;
 use crate::stmt;
+use crate::symbol::Symbol;
 use crate::token_type;
-use std::{error::Error, fmt};
+use std::cell::Cell;
+use std::rc::Rc;
 
-#[derive(Debug)]
-pub struct ParseError {
-    description: String,
-}
-impl Error for ParseError {}
-impl fmt::Display for ParseError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "ParseError: {}", self.description)
-    }
-}
+/// How deeply statements and expressions may nest inside each other. The
+/// parser and later passes recurse once per level, so this keeps them from
+/// overflowing the stack. Chains of operators, suffixes and `else if`
+/// branches are handled in loops and don't count.
+const MAX_NESTING: usize = 256;
 
 pub struct Parser {
     tokens: std::iter::Peekable<std::vec::IntoIter<token_type::Token>>,
+    errors: Vec<LoxError>,
+    nesting: usize,
+    /// How many blocks enclose the declaration being parsed.
+    blocks: usize,
 }
 impl Parser {
-    pub fn parse(tokens: Vec<token_type::Token>) -> Result<Vec<stmt::Stmt>, ParseError> {
+    pub fn parse(tokens: Vec<token_type::Token>) -> Result<Vec<stmt::Stmt>, Vec<LoxError>> {
+        let (stmts, errors) = Self::parse_partial(tokens);
+        if errors.is_empty() {
+            return Ok(stmts);
+        }
+        Err(errors)
+    }
+
+    /// Parses as much of `tokens` as possible. After an error the parser
+    /// skips to the next statement boundary and carries on, so the result
+    /// holds every statement that parsed cleanly and every error found.
+    pub fn parse_partial(tokens: Vec<token_type::Token>) -> (Vec<stmt::Stmt>, Vec<LoxError>) {
         let mut parser = Parser {
             tokens: tokens.into_iter().peekable(),
+            errors: vec![],
+            nesting: 0,
+            blocks: 0,
         };
         let mut stmts = vec![];
         while let Some(token) = parser.tokens.peek() {
             match token.r#type {
-                token_type::TokenType::Eof => return Ok(stmts),
-                _ => stmts.push(parser.declaration()?),
+                token_type::TokenType::Eof => return (stmts, parser.errors),
+                _ => stmts.extend(parser.recovering_declaration()),
+            }
+        }
+        parser.errors.push(LoxError::parse(
+            ErrorCode::ExpectedToken,
+            Span::default(),
+            "No Eof found",
+        ));
+        (stmts, parser.errors)
+    }
+
+    /// Parses a declaration, or records the error and synchronizes.
+    fn recovering_declaration(&mut self) -> Option<stmt::Stmt> {
+        let remaining = self.tokens.len();
+        match self.declaration() {
+            Ok(stmt) => Some(stmt),
+            Err(e) => {
+                self.errors.push(e);
+                // An error raised before consuming anything, such as nesting
+                // too deeply at a keyword, would otherwise be retried forever.
+                if self.tokens.len() == remaining && self.peek_type() != token_type::TokenType::Eof
+                {
+                    self.tokens.next();
+                }
+                self.synchronize();
+                None
+            }
+        }
+    }
+
+    /// Discards tokens until the end of the current statement: just past a
+    /// ';', or before a keyword that starts a statement or a '}' that closes
+    /// the enclosing block. Outside any block a '}' can only belong to the
+    /// broken statement, so it is discarded too.
+    fn synchronize(&mut self) {
+        while let Some(token) = self.tokens.peek() {
+            match token.r#type {
+                token_type::TokenType::Semicolon => {
+                    self.tokens.next();
+                    return;
+                }
+                token_type::TokenType::RightBrace if self.blocks == 0 => {
+                    self.tokens.next();
+                    return;
+                }
+                token_type::TokenType::Class
+                | token_type::TokenType::Fun
+                | token_type::TokenType::Var
+                | token_type::TokenType::For
+                | token_type::TokenType::If
+                | token_type::TokenType::While
+                | token_type::TokenType::Print
+                | token_type::TokenType::Return
+                | token_type::TokenType::RightBrace
+                | token_type::TokenType::Eof => return,
+                _ => {
+                    self.tokens.next();
+                }
             }
         }
-        Err(ParseError {
-            description: "No Eof found".to_string(),
+    }
+
+    fn declaration(&mut self) -> Result<stmt::Stmt, LoxError> {
+        self.nested(|parser| match parser.peek_type() {
+            token_type::TokenType::Var => parser.var_declaration(),
+            token_type::TokenType::Fun => {
+                parser.advance(); // consume 'fun'
+                Ok(stmt::Stmt::Function(parser.function("function")?))
+            }
+            token_type::TokenType::Class => parser.class_declaration(),
+            _ => parser.statement(),
         })
     }
 
-    fn declaration(&mut self) -> Result<stmt::Stmt, ParseError> {
-        match self.tokens.peek().unwrap().r#type {
-            token_type::TokenType::Var => self.var_declaration(),
-            _ => self.statement(),
+    fn class_declaration(&mut self) -> Result<stmt::Stmt, LoxError> {
+        let keyword = self.advance(); // consume 'class'
+        let name = self.expect_token(
+            token_type::TokenType::Identifier,
+            "Expected class name".to_string(),
+        )?;
+        let mut superclass = None;
+        if self.peek_type() == token_type::TokenType::Less {
+            self.advance(); // consume '<'
+            let name = self.expect_token(
+                token_type::TokenType::Identifier,
+                "Expected superclass name".to_string(),
+            )?;
+            superclass = Some(expr::Variable {
+                slot: Cell::new(None),
+                span: name.span,
+                name,
+            });
+        }
+        self.expect_token(
+            token_type::TokenType::LeftBrace,
+            "Expected '{' before class body".to_string(),
+        )?;
+        let mut methods = vec![];
+        while !matches!(
+            self.peek_type(),
+            token_type::TokenType::RightBrace | token_type::TokenType::Eof
+        ) {
+            methods.push(self.function("method")?);
+        }
+        let right_brace = self.expect_token(
+            token_type::TokenType::RightBrace,
+            "Expected '}' after class body".to_string(),
+        )?;
+        Ok(stmt::Stmt::Class(stmt::Class {
+            name,
+            superclass,
+            methods,
+            span: keyword.span.to(right_brace.span),
+        }))
+    }
+
+    fn function(&mut self, kind: &str) -> Result<Rc<stmt::Function>, LoxError> {
+        let name = self.expect_token(
+            token_type::TokenType::Identifier,
+            format!("Expected {} name", kind),
+        )?;
+        self.expect_token(
+            token_type::TokenType::LeftParen,
+            format!("Expected '(' after {} name", kind),
+        )?;
+        let mut params = vec![];
+        if self.peek_type() != token_type::TokenType::RightParen {
+            loop {
+                if params.len() == 255 {
+                    // Not a syntax error, so report it and keep parsing.
+                    let error = self.error(
+                        ErrorCode::TooManyParameters,
+                        "Can't have more than 255 parameters",
+                    );
+                    self.errors.push(error);
+                }
+                params.push(self.expect_token(
+                    token_type::TokenType::Identifier,
+                    "Expected parameter name".to_string(),
+                )?);
+                if self.peek_type() != token_type::TokenType::Comma {
+                    break;
+                }
+                self.advance(); // consume ','
+            }
+        }
+        self.expect_token(
+            token_type::TokenType::RightParen,
+            "Expected ')' after parameters".to_string(),
+        )?;
+        if self.peek_type() != token_type::TokenType::LeftBrace {
+            return Err(self.error(
+                ErrorCode::ExpectedToken,
+                format!("Expected '{{' before {} body", kind),
+            ));
         }
+        let (body, body_span) = self.block()?;
+        Ok(Rc::new(stmt::Function {
+            span: name.span.to(body_span),
+            name,
+            params,
+            body,
+        }))
     }
 
-    fn var_declaration(&mut self) -> Result<stmt::Stmt, ParseError> {
-        self.tokens.next(); // consume 'var'
-        let name = self.tokens.next().unwrap();
-        let expression = match self.tokens.peek().unwrap().r#type {
+    fn var_declaration(&mut self) -> Result<stmt::Stmt, LoxError> {
+        let keyword = self.advance(); // consume 'var'
+        let name = self.expect_token(
+            token_type::TokenType::Identifier,
+            "Expected variable name".to_string(),
+        )?;
+        let expression = match self.peek_type() {
             token_type::TokenType::Equal => {
-                self.tokens.next(); // consume '='
+                self.advance(); // consume '='
                 Some(self.expression()?)
             }
             _ => None,
         };
-        self.expect_token(
+        let semicolon = self.expect_token(
             token_type::TokenType::Semicolon,
             "Expected variable declaration to end with a semicolon".to_string(),
         )?;
-        Ok(stmt::Stmt::VarDec(stmt::VarDec { name, expression }))
+        Ok(stmt::Stmt::VarDec(stmt::VarDec {
+            name,
+            expression,
+            span: keyword.span.to(semicolon.span),
+        }))
     }
 
-    fn statement(&mut self) -> Result<stmt::Stmt, ParseError> {
-        match self.tokens.peek().unwrap().r#type {
-            token_type::TokenType::Print => self.print_statement(),
-            token_type::TokenType::LeftBrace => self.block_statement(),
-            token_type::TokenType::If => self.if_statement(),
-            token_type::TokenType::While => self.while_statement(),
-            token_type::TokenType::For => self.for_statement(),
-            _ => self.expression_statement(),
-        }
+    fn statement(&mut self) -> Result<stmt::Stmt, LoxError> {
+        self.nested(|parser| match parser.peek_type() {
+            token_type::TokenType::Print => parser.print_statement(),
+            token_type::TokenType::LeftBrace => parser.block_statement(),
+            token_type::TokenType::If => parser.if_statement(),
+            token_type::TokenType::While => parser.while_statement(),
+            token_type::TokenType::For => parser.for_statement(),
+            token_type::TokenType::Return => parser.return_statement(),
+            token_type::TokenType::Break => {
+                let keyword = parser.advance(); // consume 'break'
+                let semicolon = parser.expect_token(
+                    token_type::TokenType::Semicolon,
+                    "Expected ';' after 'break'".to_string(),
+                )?;
+                Ok(stmt::Stmt::Break(stmt::Break {
+                    span: keyword.span.to(semicolon.span),
+                    keyword,
+                }))
+            }
+            token_type::TokenType::Continue => {
+                let keyword = parser.advance(); // consume 'continue'
+                let semicolon = parser.expect_token(
+                    token_type::TokenType::Semicolon,
+                    "Expected ';' after 'continue'".to_string(),
+                )?;
+                Ok(stmt::Stmt::Continue(stmt::Continue {
+                    span: keyword.span.to(semicolon.span),
+                    keyword,
+                }))
+            }
+            _ => parser.expression_statement(),
+        })
+    }
+
+    fn return_statement(&mut self) -> Result<stmt::Stmt, LoxError> {
+        let keyword = self.advance(); // consume 'return'
+        let value = match self.peek_type() {
+            token_type::TokenType::Semicolon => None,
+            _ => Some(self.expression()?),
+        };
+        let semicolon = self.expect_token(
+            token_type::TokenType::Semicolon,
+            "Expected return statement to end with a semicolon".to_string(),
+        )?;
+        Ok(stmt::Stmt::Return(stmt::Return {
+            span: keyword.span.to(semicolon.span),
+            keyword,
+            value,
+        }))
     }
 
-    fn for_statement(&mut self) -> Result<stmt::Stmt, ParseError> {
-        self.tokens.next(); // consume 'for'
+    fn for_statement(&mut self) -> Result<stmt::Stmt, LoxError> {
+        let keyword = self.advance(); // consume 'for'
         self.expect_token(
             token_type::TokenType::LeftParen,
             "Expected '(' after 'for'".to_string(),
         )?;
-        let initializer = match self.tokens.peek().unwrap().r#type {
-            token_type::TokenType::Semicolon => None,
+        let initializer = match self.peek_type() {
+            token_type::TokenType::Semicolon => {
+                self.advance(); // consume ';'
+                None
+            }
             token_type::TokenType::Var => Some(self.var_declaration()?),
             _ => Some(self.expression_statement()?),
         };
-        let condition = match self.tokens.peek().unwrap().r#type {
+        let condition = match self.peek_type() {
             token_type::TokenType::Semicolon => None,
             _ => Some(self.expression()?),
         };
@@ -88,7 +304,7 @@ impl Parser {
             token_type::TokenType::Semicolon,
             "Expected ';' after for condition".to_string(),
         )?;
-        let increment = match self.tokens.peek().unwrap().r#type {
+        let increment = match self.peek_type() {
             token_type::TokenType::RightParen => None,
             _ => Some(self.expression()?),
         };
@@ -96,36 +312,34 @@ impl Parser {
             token_type::TokenType::RightParen,
             "Expected ')' after for increment expression".to_string(),
         )?;
-        let mut body = self.statement()?;
+        let body = self.statement()?;
+        // Nodes synthesized by the desugaring cover the whole loop.
+        let span = keyword.span.to(body.span());
 
-        if let Some(increment) = increment {
-            body = stmt::Stmt::Block(stmt::Block {
-                statements: vec![
-                    body,
-                    stmt::Stmt::Expr(stmt::Expr {
-                        expression: increment,
-                    }),
-                ],
-            })
-        };
         let condition = match condition {
             Some(condition) => condition,
-            None => expr::Expr::Literal(expr::Literal::Bool(true)),
+            None => expr::Expr::Literal(Box::new(expr::LiteralExpr {
+                value: expr::Literal::Bool(true),
+                span: keyword.span,
+            })),
         };
-        body = stmt::Stmt::While(stmt::While {
+        let mut body = stmt::Stmt::While(stmt::While {
             condition,
             body: Box::new(body),
+            increment,
+            span,
         });
         if let Some(initializer) = initializer {
             body = stmt::Stmt::Block(stmt::Block {
                 statements: vec![initializer, body],
+                span,
             });
         };
         Ok(body)
     }
 
-    fn while_statement(&mut self) -> Result<stmt::Stmt, ParseError> {
-        self.tokens.next(); // consume 'while'
+    fn while_statement(&mut self) -> Result<stmt::Stmt, LoxError> {
+        let keyword = self.advance(); // consume 'while'
         self.expect_token(
             token_type::TokenType::LeftParen,
             "Expected '(' after 'while'".to_string(),
@@ -138,131 +352,225 @@ impl Parser {
         let body = self.statement()?;
         Ok(stmt::Stmt::While(stmt::While {
             condition,
+            span: keyword.span.to(body.span()),
             body: Box::new(body),
+            increment: None,
         }))
     }
 
+    /// The type of the next token. Running out of tokens reads as `Eof`.
+    fn peek_type(&mut self) -> token_type::TokenType {
+        match self.tokens.peek() {
+            Some(token) => token.r#type,
+            None => token_type::TokenType::Eof,
+        }
+    }
+
+    /// Consumes the next token. `Eof` is never consumed, so callers that
+    /// advance past the end keep getting it back.
+    fn advance(&mut self) -> token_type::Token {
+        if let Some(token) = self
+            .tokens
+            .next_if(|token| token.r#type != token_type::TokenType::Eof)
+        {
+            return token;
+        }
+        token_type::Token {
+            r#type: token_type::TokenType::Eof,
+            lexeme: Symbol::intern(""),
+            literal: None,
+            span: self
+                .tokens
+                .peek()
+                .map(|token| token.span)
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Runs `rule` one level deeper.
+    fn nested<T>(
+        &mut self,
+        rule: impl FnOnce(&mut Self) -> Result<T, LoxError>,
+    ) -> Result<T, LoxError> {
+        if self.nesting >= MAX_NESTING {
+            return Err(self.error(
+                ErrorCode::TooDeeplyNested,
+                format!("Can't nest more than {} levels deep", MAX_NESTING),
+            ));
+        }
+        self.nesting += 1;
+        let result = rule(self);
+        self.nesting -= 1;
+        result
+    }
+
     fn expect_token(
         &mut self,
         expected: token_type::TokenType,
         err_msg: String,
-    ) -> Result<token_type::Token, ParseError> {
-        match self.tokens.peek() {
-            Some(token) => match &token.r#type {
-                x if *x == expected => Ok(self.tokens.next().unwrap()),
-                _ => Err(ParseError {
-                    description: err_msg,
-                }),
-            },
-            None => Err(ParseError {
-                description: format!("Expected {} but found nothing", expected),
-            }),
+    ) -> Result<token_type::Token, LoxError> {
+        if self.peek_type() == expected {
+            return Ok(self.advance());
         }
+        Err(self.error(ErrorCode::ExpectedToken, err_msg))
     }
 
-    fn if_statement(&mut self) -> Result<stmt::Stmt, ParseError> {
-        self.tokens.next(); // consume 'if'
-        self.expect_token(
-            token_type::TokenType::LeftParen,
-            "Expected '(' after 'if'".to_string(),
-        )?;
-        let condition = self.expression()?;
-        self.expect_token(
-            token_type::TokenType::RightParen,
-            "Expected ')' after if condition".to_string(),
-        )?;
-        let then_branch = Box::new(self.statement()?);
-        let mut else_branch = None;
-        if let token_type::TokenType::Else = self.tokens.peek().unwrap().r#type {
-            self.tokens.next(); // consume 'else'
-            else_branch = Some(Box::new(self.statement()?));
+    /// Builds an error pointing at the next unconsumed token.
+    fn error(&mut self, code: ErrorCode, message: impl Into<String>) -> LoxError {
+        let span = match self.tokens.peek() {
+            Some(token) => token.span,
+            None => Span::default(),
         };
-        Ok(stmt::Stmt::If(stmt::If {
-            condition,
-            then_branch,
-            else_branch,
-        }))
+        LoxError::parse(code, span, message)
+    }
+
+    /// Parses an `if` and any `else if` branches after it in a loop, then
+    /// nests them, so a long ladder doesn't recurse.
+    fn if_statement(&mut self) -> Result<stmt::Stmt, LoxError> {
+        let mut ifs = vec![];
+        let mut else_branch = None;
+        loop {
+            let keyword = self.advance(); // consume 'if'
+            self.expect_token(
+                token_type::TokenType::LeftParen,
+                "Expected '(' after 'if'".to_string(),
+            )?;
+            let condition = self.expression()?;
+            self.expect_token(
+                token_type::TokenType::RightParen,
+                "Expected ')' after if condition".to_string(),
+            )?;
+            ifs.push((keyword, condition, Box::new(self.statement()?)));
+            if self.peek_type() != token_type::TokenType::Else {
+                break;
+            }
+            self.advance(); // consume 'else'
+            if self.peek_type() != token_type::TokenType::If {
+                else_branch = Some(Box::new(self.statement()?));
+                break;
+            }
+        }
+        for (keyword, condition, then_branch) in ifs.into_iter().rev() {
+            let end = match &else_branch {
+                Some(else_branch) => else_branch.span(),
+                None => then_branch.span(),
+            };
+            else_branch = Some(Box::new(stmt::Stmt::If(stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+                span: keyword.span.to(end),
+            })));
+        }
+        Ok(*else_branch.expect("The loop parses at least one 'if'"))
     }
 
-    fn print_statement(&mut self) -> Result<stmt::Stmt, ParseError> {
-        self.tokens.next(); // consume 'print'
+    fn print_statement(&mut self) -> Result<stmt::Stmt, LoxError> {
+        let keyword = self.advance(); // consume 'print'
         let value = self.expression()?;
-        self.expect_token(
+        let semicolon = self.expect_token(
             token_type::TokenType::Semicolon,
             "Expected print statement to end with a semicolon".to_string(),
         )?;
-        Ok(stmt::Stmt::Print(stmt::Print { expression: value }))
+        Ok(stmt::Stmt::Print(stmt::Print {
+            expression: value,
+            span: keyword.span.to(semicolon.span),
+        }))
+    }
+
+    fn block_statement(&mut self) -> Result<stmt::Stmt, LoxError> {
+        let (statements, span) = self.block()?;
+        Ok(stmt::Stmt::Block(stmt::Block { statements, span }))
     }
 
-    fn block_statement(&mut self) -> Result<stmt::Stmt, ParseError> {
-        self.tokens.next(); // consume '{'
+    /// Parses the declarations between a pair of braces, returning them with
+    /// the span of the whole block.
+    fn block(&mut self) -> Result<(Vec<stmt::Stmt>, Span), LoxError> {
+        let left_brace = self.advance(); // consume '{'
         let mut statements = vec![];
-        while let Some(token) = self.tokens.peek() {
-            match token.r#type {
-                token_type::TokenType::RightBrace => {
-                    self.tokens.next(); // consume '}'
-                    return Ok(stmt::Stmt::Block(stmt::Block { statements }));
-                }
+        self.blocks += 1;
+        let right_brace = loop {
+            match self.peek_type() {
+                token_type::TokenType::RightBrace => break Ok(self.advance()), // consume '}'
                 token_type::TokenType::Eof => {
-                    return Err(ParseError {
-                        description: "Expected '}' after block".to_string(),
-                    })
+                    break Err(self.error(ErrorCode::ExpectedToken, "Expected '}' after block"))
                 }
-                _ => statements.push(self.declaration()?),
+                _ => statements.extend(self.recovering_declaration()),
             }
-        }
-        return Err(ParseError {
-            description: "Expected '}' after block".to_string(),
-        });
+        };
+        self.blocks -= 1;
+        Ok((statements, left_brace.span.to(right_brace?.span)))
     }
 
-    fn expression_statement(&mut self) -> Result<stmt::Stmt, ParseError> {
+    fn expression_statement(&mut self) -> Result<stmt::Stmt, LoxError> {
         let value = self.expression()?;
-        self.expect_token(
+        let semicolon = self.expect_token(
             token_type::TokenType::Semicolon,
             "Expected expression statement to end with a semicolon".to_string(),
         )?;
-        Ok(stmt::Stmt::Expr(stmt::Expr { expression: value }))
+        Ok(stmt::Stmt::Expr(stmt::Expr {
+            span: value.span().to(semicolon.span),
+            expression: value,
+        }))
     }
 
-    fn expression(&mut self) -> Result<expr::Expr, ParseError> {
-        self.assignment()
+    fn expression(&mut self) -> Result<expr::Expr, LoxError> {
+        self.nested(Self::assignment)
     }
 
-    fn assignment(&mut self) -> Result<expr::Expr, ParseError> {
+    fn assignment(&mut self) -> Result<expr::Expr, LoxError> {
         let expr = self.or()?;
         if let Some(token) = self.tokens.peek() {
-            match token.r#type {
-                token_type::TokenType::Equal => {
-                    self.tokens.next(); // consume '='
-                    let value = self.assignment()?;
-                    match expr {
-                        expr::Expr::Variable(variable) => {
-                            return Ok(expr::Expr::Assign(Box::new(expr::Assign {
-                                name: variable.name,
-                                value,
-                            })))
-                        }
-                        _ => {
-                            return Err(ParseError {
-                                description: "Invalid assignment target".to_string(),
-                            })
-                        }
+            if token.r#type == token_type::TokenType::Equal {
+                let equals = self.advance(); // consume '='
+                let value = self.nested(Self::assignment)?;
+                let span = expr.span().to(value.span());
+                match expr {
+                    expr::Expr::Variable(variable) => {
+                        return Ok(expr::Expr::Assign(Box::new(expr::Assign {
+                            slot: Cell::new(None),
+                            name: variable.name,
+                            value,
+                            span,
+                        })))
+                    }
+                    expr::Expr::Get(mut get) => {
+                        return Ok(expr::Expr::Set(Box::new(expr::Set {
+                            object: get.object.take(),
+                            name: get.name.clone(),
+                            value,
+                            span,
+                        })))
+                    }
+                    expr::Expr::Index(mut index) => {
+                        return Ok(expr::Expr::SetIndex(Box::new(expr::SetIndex {
+                            object: index.object.take(),
+                            index: index.index.take(),
+                            value,
+                            span,
+                        })))
+                    }
+                    _ => {
+                        return Err(LoxError::parse(
+                            ErrorCode::InvalidAssignmentTarget,
+                            equals.span,
+                            "Invalid assignment target",
+                        ))
                     }
                 }
-                _ => {}
             }
         }
         Ok(expr)
     }
 
-    fn or(&mut self) -> Result<expr::Expr, ParseError> {
+    fn or(&mut self) -> Result<expr::Expr, LoxError> {
         let mut expr = self.and()?;
-        while let token_type::TokenType::Or = self.tokens.peek().unwrap().r#type {
-            let operator = self.tokens.next().unwrap();
+        while let token_type::TokenType::Or = self.peek_type() {
+            let operator = self.advance();
             let right = self.and()?;
             expr = expr::Expr::Logical(Box::new(expr::Logical {
                 operator,
+                span: expr.span().to(right.span()),
                 left: expr,
                 right,
             }));
@@ -270,13 +578,14 @@ impl Parser {
         Ok(expr)
     }
 
-    fn and(&mut self) -> Result<expr::Expr, ParseError> {
+    fn and(&mut self) -> Result<expr::Expr, LoxError> {
         let mut expr = self.equality()?;
-        while let token_type::TokenType::And = self.tokens.peek().unwrap().r#type {
-            let operator = self.tokens.next().unwrap();
+        while let token_type::TokenType::And = self.peek_type() {
+            let operator = self.advance();
             let right = self.equality()?;
             expr = expr::Expr::Logical(Box::new(expr::Logical {
                 operator,
+                span: expr.span().to(right.span()),
                 left: expr,
                 right,
             }));
@@ -284,18 +593,16 @@ impl Parser {
         Ok(expr)
     }
 
-    fn equality(&mut self) -> Result<expr::Expr, ParseError> {
-        let mut expr = match self.comparison() {
-            Ok(expr) => expr,
-            Err(e) => return Err(e),
-        };
+    fn equality(&mut self) -> Result<expr::Expr, LoxError> {
+        let mut expr = self.comparison()?;
         while let Some(token) = self.tokens.peek() {
             match token.r#type {
                 token_type::TokenType::BangEqual | token_type::TokenType::EqualEqual => {
-                    let operator = self.tokens.next().unwrap();
+                    let operator = self.advance();
                     match self.comparison() {
                         Ok(right) => {
                             expr = expr::Expr::Binary(Box::new(expr::Binary {
+                                span: expr.span().to(right.span()),
                                 left: expr,
                                 operator,
                                 right,
@@ -310,7 +617,7 @@ impl Parser {
         Ok(expr)
     }
 
-    fn comparison(&mut self) -> Result<expr::Expr, ParseError> {
+    fn comparison(&mut self) -> Result<expr::Expr, LoxError> {
         let mut expr = self.term()?;
         while let Some(token) = self.tokens.peek() {
             match token.r#type {
@@ -318,10 +625,11 @@ impl Parser {
                 | token_type::TokenType::GreaterEqual
                 | token_type::TokenType::Less
                 | token_type::TokenType::LessEqual => {
-                    let operator = self.tokens.next().unwrap();
+                    let operator = self.advance();
                     match self.term() {
                         Ok(right) => {
                             expr = expr::Expr::Binary(Box::new(expr::Binary {
+                                span: expr.span().to(right.span()),
                                 left: expr,
                                 operator,
                                 right,
@@ -336,18 +644,16 @@ impl Parser {
         Ok(expr)
     }
 
-    fn term(&mut self) -> Result<expr::Expr, ParseError> {
-        let mut expr = match self.factor() {
-            Ok(expr) => expr,
-            Err(e) => return Err(e),
-        };
+    fn term(&mut self) -> Result<expr::Expr, LoxError> {
+        let mut expr = self.factor()?;
         while let Some(token) = self.tokens.peek() {
             match token.r#type {
                 token_type::TokenType::Minus | token_type::TokenType::Plus => {
-                    let operator = self.tokens.next().unwrap();
+                    let operator = self.advance();
                     match self.factor() {
                         Ok(right) => {
                             expr = expr::Expr::Binary(Box::new(expr::Binary {
+                                span: expr.span().to(right.span()),
                                 left: expr,
                                 operator,
                                 right,
@@ -362,18 +668,16 @@ impl Parser {
         Ok(expr)
     }
 
-    fn factor(&mut self) -> Result<expr::Expr, ParseError> {
-        let mut expr = match self.unary() {
-            Ok(expr) => expr,
-            Err(e) => return Err(e),
-        };
+    fn factor(&mut self) -> Result<expr::Expr, LoxError> {
+        let mut expr = self.unary()?;
         while let Some(token) = self.tokens.peek() {
             match token.r#type {
                 token_type::TokenType::Slash | token_type::TokenType::Star => {
-                    let operator = self.tokens.next().unwrap();
+                    let operator = self.advance();
                     match self.unary() {
                         Ok(right) => {
                             expr = expr::Expr::Binary(Box::new(expr::Binary {
+                                span: expr.span().to(right.span()),
                                 left: expr,
                                 operator,
                                 right,
@@ -388,14 +692,15 @@ impl Parser {
         Ok(expr)
     }
 
-    fn unary(&mut self) -> Result<expr::Expr, ParseError> {
+    fn unary(&mut self) -> Result<expr::Expr, LoxError> {
         if let Some(token) = self.tokens.peek() {
             match token.r#type {
                 token_type::TokenType::Bang | token_type::TokenType::Minus => {
-                    let operator = self.tokens.next().unwrap();
-                    match self.unary() {
+                    let operator = self.advance();
+                    match self.nested(Self::unary) {
                         Ok(right) => {
                             return Ok(expr::Expr::Unary(Box::new(expr::Unary {
+                                span: operator.span.to(right.span()),
                                 operator,
                                 right,
                             })));
@@ -406,59 +711,209 @@ impl Parser {
                 _ => {}
             }
         }
-        self.primary()
+        self.call()
     }
 
-    fn primary(&mut self) -> Result<expr::Expr, ParseError> {
-        let token = match self.tokens.next() {
-            Some(token) => token,
-            None => panic!("Expected a token"),
+    fn call(&mut self) -> Result<expr::Expr, LoxError> {
+        let mut expr = self.primary()?;
+        loop {
+            match self.peek_type() {
+                token_type::TokenType::LeftParen => {
+                    self.advance(); // consume '('
+                    expr = self.finish_call(expr)?;
+                }
+                token_type::TokenType::Dot => {
+                    self.advance(); // consume '.'
+                    let name = self.expect_token(
+                        token_type::TokenType::Identifier,
+                        "Expected property name after '.'".to_string(),
+                    )?;
+                    expr = expr::Expr::Get(Box::new(expr::Get {
+                        span: expr.span().to(name.span),
+                        object: expr,
+                        name,
+                    }));
+                }
+                token_type::TokenType::LeftBracket => {
+                    self.advance(); // consume '['
+                    let index = self.expression()?;
+                    let bracket = self.expect_token(
+                        token_type::TokenType::RightBracket,
+                        "Expected ']' after index".to_string(),
+                    )?;
+                    expr = expr::Expr::Index(Box::new(expr::Index {
+                        span: expr.span().to(bracket.span),
+                        object: expr,
+                        index,
+                    }));
+                }
+                _ => break,
+            }
+        }
+        Ok(expr)
+    }
+
+    fn finish_call(&mut self, callee: expr::Expr) -> Result<expr::Expr, LoxError> {
+        let mut arguments = vec![];
+        if self.peek_type() != token_type::TokenType::RightParen {
+            loop {
+                if arguments.len() == 255 {
+                    let error = self.error(
+                        ErrorCode::TooManyArguments,
+                        "Can't have more than 255 arguments",
+                    );
+                    self.errors.push(error);
+                }
+                arguments.push(self.expression()?);
+                if self.peek_type() != token_type::TokenType::Comma {
+                    break;
+                }
+                self.advance(); // consume ','
+            }
+        }
+        let paren = self.expect_token(
+            token_type::TokenType::RightParen,
+            "Expected ')' after arguments".to_string(),
+        )?;
+        Ok(expr::Expr::Call(Box::new(expr::Call {
+            span: callee.span().to(paren.span),
+            callee,
+            paren,
+            arguments,
+        })))
+    }
+
+    fn primary(&mut self) -> Result<expr::Expr, LoxError> {
+        let token = self.advance();
+        let literal = |value| {
+            Ok(expr::Expr::Literal(Box::new(expr::LiteralExpr {
+                value,
+                span: token.span,
+            })))
         };
         match token.r#type {
-            token_type::TokenType::False => Ok(expr::Expr::Literal(expr::Literal::Bool(false))),
-            token_type::TokenType::True => Ok(expr::Expr::Literal(expr::Literal::Bool(true))),
-            token_type::TokenType::Nil => Ok(expr::Expr::Literal(expr::Literal::Nil)),
-            token_type::TokenType::Number => match token.literal.as_ref().unwrap() {
-                token_type::Literal::Number(x) => {
-                    Ok(expr::Expr::Literal(expr::Literal::Number(*x)))
-                }
-                _ => Err(ParseError {
-                    description: "Number token did not contain a Number".to_string(),
-                }),
+            token_type::TokenType::False => literal(expr::Literal::Bool(false)),
+            token_type::TokenType::True => literal(expr::Literal::Bool(true)),
+            token_type::TokenType::Nil => literal(expr::Literal::Nil),
+            token_type::TokenType::Number => match &token.literal {
+                Some(token_type::Literal::Number(x)) => literal(expr::Literal::Number(*x)),
+                _ => Err(LoxError::parse(
+                    ErrorCode::ExpectedExpression,
+                    token.span,
+                    "Number token did not contain a Number",
+                )),
             },
-            token_type::TokenType::String => match token.literal.as_ref().unwrap() {
-                token_type::Literal::String(x) => {
-                    Ok(expr::Expr::Literal(expr::Literal::String(x.clone())))
-                }
-                _ => Err(ParseError {
-                    description: "String token did not contain a String".to_string(),
-                }),
+            token_type::TokenType::String => match &token.literal {
+                Some(token_type::Literal::String(x)) => literal(expr::Literal::String(x.clone())),
+                _ => Err(LoxError::parse(
+                    ErrorCode::ExpectedExpression,
+                    token.span,
+                    "String token did not contain a String",
+                )),
             },
             token_type::TokenType::Identifier => {
                 Ok(expr::Expr::Variable(Box::new(expr::Variable {
+                    slot: Cell::new(None),
+                    span: token.span,
                     name: token,
                 })))
             }
+            token_type::TokenType::This => Ok(expr::Expr::This(Box::new(expr::This {
+                slot: Cell::new(None),
+                span: token.span,
+                keyword: token,
+            }))),
+            token_type::TokenType::Super => {
+                self.expect_token(
+                    token_type::TokenType::Dot,
+                    "Expected '.' after 'super'".to_string(),
+                )?;
+                let method = self.expect_token(
+                    token_type::TokenType::Identifier,
+                    "Expected superclass method name".to_string(),
+                )?;
+                Ok(expr::Expr::Super(Box::new(expr::Super {
+                    slot: Cell::new(None),
+                    span: token.span.to(method.span),
+                    keyword: token,
+                    method,
+                })))
+            }
+            token_type::TokenType::LeftBracket => {
+                let mut elements = vec![];
+                if self.peek_type() != token_type::TokenType::RightBracket {
+                    loop {
+                        elements.push(self.expression()?);
+                        if self.peek_type() != token_type::TokenType::Comma {
+                            break;
+                        }
+                        self.advance(); // consume ','
+                    }
+                }
+                let bracket = self.expect_token(
+                    token_type::TokenType::RightBracket,
+                    "Expected ']' after list elements".to_string(),
+                )?;
+                Ok(expr::Expr::List(Box::new(expr::List {
+                    elements,
+                    span: token.span.to(bracket.span),
+                })))
+            }
+            token_type::TokenType::LeftBrace => {
+                // In expression position a brace opens a map, never a block.
+                let mut entries = vec![];
+                if self.peek_type() != token_type::TokenType::RightBrace {
+                    loop {
+                        let key = self.expression()?;
+                        self.expect_token(
+                            token_type::TokenType::Colon,
+                            "Expected ':' after map key".to_string(),
+                        )?;
+                        entries.push((key, self.expression()?));
+                        if self.peek_type() != token_type::TokenType::Comma {
+                            break;
+                        }
+                        self.advance(); // consume ','
+                    }
+                }
+                let brace = self.expect_token(
+                    token_type::TokenType::RightBrace,
+                    "Expected '}' after map entries".to_string(),
+                )?;
+                Ok(expr::Expr::Map(Box::new(expr::Map {
+                    entries,
+                    span: token.span.to(brace.span),
+                })))
+            }
             token_type::TokenType::LeftParen => {
                 match self.expression() {
                     Ok(expr) => {
-                        if self.tokens.peek().unwrap().r#type == token_type::TokenType::RightParen {
-                            self.tokens.next(); // consume RightParen
+                        if self.peek_type() == token_type::TokenType::RightParen {
+                            let paren = self.advance(); // consume RightParen
                             Ok(expr::Expr::Grouping(Box::new(expr::Grouping {
                                 expression: expr,
+                                span: token.span.to(paren.span),
                             })))
                         } else {
-                            Err(ParseError {
-                                description: "Grouping did not end in right paren".to_string(),
-                            })
+                            Err(self.error(
+                                ErrorCode::ExpectedToken,
+                                "Grouping did not end in right paren",
+                            ))
                         }
                     }
                     Err(e) => Err(e),
                 }
             }
-            _ => Err(ParseError {
-                description: format!("Unexpected token: {} ({})", token, token.r#type),
-            }),
+            token_type::TokenType::Eof => Err(LoxError::parse(
+                ErrorCode::ExpectedExpression,
+                token.span,
+                "Expected expression but found end of input",
+            )),
+            _ => Err(LoxError::parse(
+                ErrorCode::ExpectedExpression,
+                token.span,
+                format!("Unexpected token: {} ({})", token, token.r#type),
+            )),
         }
     }
 }
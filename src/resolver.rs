@@ -0,0 +1,365 @@
+use crate::error::{ErrorCode, LoxError};
+use crate::expr;
+use crate::stmt;
+use crate::symbol::Symbol;
+use crate::token_type;
+use std::cell::Cell;
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionType {
+    None,
+    Function,
+    Method,
+    Initializer,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ClassType {
+    None,
+    Class,
+    Subclass,
+}
+
+/// Where a local variable lives: `depth` scopes up from its use, in slot
+/// `index` of that scope.
+#[derive(Clone, Copy)]
+pub struct Slot {
+    pub depth: usize,
+    pub index: usize,
+}
+
+struct Variable {
+    defined: bool, // false while the variable's initializer is resolved
+    slot: usize,
+}
+
+/// Static pass run between parsing and interpreting. Records the slot of each
+/// local variable use on the expression itself, so the slots go away with the
+/// code. Variables left without one are globals.
+pub struct Resolver {
+    scopes: Vec<HashMap<Symbol, Variable>>,
+    errors: Vec<LoxError>,
+    current_function: FunctionType,
+    current_class: ClassType,
+    loop_depth: usize, // loops enclosing the current statement within its function
+}
+impl Resolver {
+    pub fn resolve(stmts: &[stmt::Stmt]) -> Result<(), Vec<LoxError>> {
+        let mut resolver = Resolver {
+            scopes: vec![],
+            errors: vec![],
+            current_function: FunctionType::None,
+            current_class: ClassType::None,
+            loop_depth: 0,
+        };
+        resolver.stmts(stmts);
+        if resolver.errors.is_empty() {
+            return Ok(());
+        }
+        Err(resolver.errors)
+    }
+
+    fn stmts(&mut self, stmts: &[stmt::Stmt]) {
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &stmt::Stmt) {
+        match stmt {
+            stmt::Stmt::Expr(expr_stmt) => self.expr(&expr_stmt.expression),
+            stmt::Stmt::Print(print_stmt) => self.expr(&print_stmt.expression),
+            stmt::Stmt::VarDec(var_stmt) => {
+                self.declare(&var_stmt.name);
+                if let Some(expression) = &var_stmt.expression {
+                    self.expr(expression);
+                }
+                self.define(&var_stmt.name);
+            }
+            stmt::Stmt::Block(block_stmt) if block_stmt.has_scope() => {
+                self.begin_scope();
+                self.stmts(&block_stmt.statements);
+                self.end_scope();
+            }
+            stmt::Stmt::Block(block_stmt) => self.stmts(&block_stmt.statements),
+            stmt::Stmt::If(if_stmt) => {
+                let (ifs, else_branch) = if_stmt.ladder();
+                for if_stmt in ifs {
+                    self.expr(&if_stmt.condition);
+                    self.stmt(&if_stmt.then_branch);
+                }
+                if let Some(else_branch) = else_branch {
+                    self.stmt(else_branch);
+                }
+            }
+            stmt::Stmt::While(while_stmt) => {
+                self.expr(&while_stmt.condition);
+                self.loop_depth += 1;
+                self.stmt(&while_stmt.body);
+                self.loop_depth -= 1;
+                if let Some(increment) = &while_stmt.increment {
+                    self.expr(increment);
+                }
+            }
+            stmt::Stmt::Break(break_stmt) => {
+                if self.loop_depth == 0 {
+                    self.error(
+                        &break_stmt.keyword,
+                        ErrorCode::BreakOutsideLoop,
+                        "Can't use 'break' outside of a loop",
+                    );
+                }
+            }
+            stmt::Stmt::Continue(continue_stmt) => {
+                if self.loop_depth == 0 {
+                    self.error(
+                        &continue_stmt.keyword,
+                        ErrorCode::ContinueOutsideLoop,
+                        "Can't use 'continue' outside of a loop",
+                    );
+                }
+            }
+            stmt::Stmt::Function(function_stmt) => {
+                self.declare(&function_stmt.name);
+                self.define(&function_stmt.name);
+                self.function(function_stmt, FunctionType::Function);
+            }
+            stmt::Stmt::Return(return_stmt) => {
+                if self.current_function == FunctionType::None {
+                    self.error(
+                        &return_stmt.keyword,
+                        ErrorCode::TopLevelReturn,
+                        "Can't return from top-level code",
+                    );
+                }
+                if let Some(value) = &return_stmt.value {
+                    if self.current_function == FunctionType::Initializer {
+                        self.error(
+                            &return_stmt.keyword,
+                            ErrorCode::InitializerReturn,
+                            "Can't return a value from an initializer",
+                        );
+                    }
+                    self.expr(value);
+                }
+            }
+            stmt::Stmt::Class(class_stmt) => {
+                let enclosing_class = self.current_class;
+                self.current_class = ClassType::Class;
+                self.declare(&class_stmt.name);
+                self.define(&class_stmt.name);
+                if let Some(superclass) = &class_stmt.superclass {
+                    if superclass.name.lexeme == class_stmt.name.lexeme {
+                        self.error(
+                            &superclass.name,
+                            ErrorCode::SelfInheritance,
+                            "A class can't inherit from itself",
+                        );
+                    }
+                    self.current_class = ClassType::Subclass;
+                    self.resolve_local(&superclass.slot, &superclass.name);
+                    self.begin_scope();
+                    self.implicit(Symbol::intern("super"));
+                }
+                self.begin_scope();
+                self.implicit(Symbol::intern("this"));
+                for method in &class_stmt.methods {
+                    let r#type = match method.name.lexeme.as_str() {
+                        "init" => FunctionType::Initializer,
+                        _ => FunctionType::Method,
+                    };
+                    self.function(method, r#type);
+                }
+                self.end_scope();
+                if class_stmt.superclass.is_some() {
+                    self.end_scope();
+                }
+                self.current_class = enclosing_class;
+            }
+        }
+    }
+
+    fn function(&mut self, function: &stmt::Function, r#type: FunctionType) {
+        let enclosing_function = self.current_function;
+        let enclosing_loops = self.loop_depth;
+        self.current_function = r#type;
+        self.loop_depth = 0;
+        self.begin_scope();
+        for param in &function.params {
+            self.declare(param);
+            self.define(param);
+        }
+        self.stmts(&function.body);
+        self.end_scope();
+        self.current_function = enclosing_function;
+        self.loop_depth = enclosing_loops;
+    }
+
+    fn expr(&mut self, expr: &expr::Expr) {
+        match expr {
+            expr::Expr::Literal(_) => {}
+            expr::Expr::Grouping(grouping) => self.expr(&grouping.expression),
+            expr::Expr::Unary(unary) => self.expr(&unary.right),
+            expr::Expr::Binary(_)
+            | expr::Expr::Logical(_)
+            | expr::Expr::Call(_)
+            | expr::Expr::Get(_)
+            | expr::Expr::Index(_) => {
+                let (head, links) = expr.chain();
+                self.expr(head);
+                for link in links {
+                    self.link(link);
+                }
+            }
+            expr::Expr::Variable(variable) => {
+                if let Some(Variable { defined: false, .. }) = self
+                    .scopes
+                    .last()
+                    .and_then(|scope| scope.get(&variable.name.lexeme))
+                {
+                    self.error(
+                        &variable.name,
+                        ErrorCode::SelfReferencingInitializer,
+                        "Can't read local variable in its own initializer",
+                    );
+                }
+                self.resolve_local(&variable.slot, &variable.name);
+            }
+            expr::Expr::Assign(assign) => {
+                self.expr(&assign.value);
+                self.resolve_local(&assign.slot, &assign.name);
+            }
+            expr::Expr::Set(set) => {
+                self.expr(&set.value);
+                self.expr(&set.object);
+            }
+            expr::Expr::This(this) => {
+                if self.current_class == ClassType::None {
+                    self.error(
+                        &this.keyword,
+                        ErrorCode::ThisOutsideClass,
+                        "Can't use 'this' outside of a class",
+                    );
+                    return;
+                }
+                self.resolve_local(&this.slot, &this.keyword);
+            }
+            expr::Expr::Super(super_expr) => {
+                match self.current_class {
+                    ClassType::None => self.error(
+                        &super_expr.keyword,
+                        ErrorCode::SuperOutsideClass,
+                        "Can't use 'super' outside of a class",
+                    ),
+                    ClassType::Class => self.error(
+                        &super_expr.keyword,
+                        ErrorCode::SuperWithoutSuperclass,
+                        "Can't use 'super' in a class with no superclass",
+                    ),
+                    ClassType::Subclass => {}
+                }
+                self.resolve_local(&super_expr.slot, &super_expr.keyword);
+            }
+            expr::Expr::List(list) => {
+                for element in &list.elements {
+                    self.expr(element);
+                }
+            }
+            expr::Expr::Map(map) => {
+                for (key, value) in &map.entries {
+                    self.expr(key);
+                    self.expr(value);
+                }
+            }
+            expr::Expr::SetIndex(set_index) => {
+                self.expr(&set_index.value);
+                self.expr(&set_index.object);
+                self.expr(&set_index.index);
+            }
+        }
+    }
+
+    /// Resolves a chain link apart from its left-hand side.
+    fn link(&mut self, link: &expr::Expr) {
+        match link {
+            expr::Expr::Binary(binary) => self.expr(&binary.right),
+            expr::Expr::Logical(logical) => self.expr(&logical.right),
+            expr::Expr::Call(call) => {
+                for argument in &call.arguments {
+                    self.expr(argument);
+                }
+            }
+            expr::Expr::Get(_) => {}
+            expr::Expr::Index(index) => self.expr(&index.index),
+            _ => unreachable!("Not a chain link"),
+        }
+    }
+
+    fn resolve_local(&mut self, slot: &Cell<Option<Slot>>, name: &token_type::Token) {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some(variable) = scope.get(&name.lexeme) {
+                let index = variable.slot;
+                slot.set(Some(Slot { depth, index }));
+                return;
+            }
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn declare(&mut self, name: &token_type::Token) {
+        let Some(scope) = self.scopes.last_mut() else {
+            return;
+        };
+        let variable = Variable {
+            defined: false,
+            slot: scope.len(),
+        };
+        if scope.insert(name.lexeme.clone(), variable).is_some() {
+            self.error(
+                name,
+                ErrorCode::DuplicateDeclaration,
+                "Already a variable with this name in this scope",
+            );
+        }
+    }
+
+    fn define(&mut self, name: &token_type::Token) {
+        if let Some(variable) = self
+            .scopes
+            .last_mut()
+            .and_then(|scope| scope.get_mut(&name.lexeme))
+        {
+            variable.defined = true;
+        }
+    }
+
+    /// Declares a variable the interpreter binds itself, like `this`, as the
+    /// only one in a new scope.
+    fn implicit(&mut self, name: Symbol) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(
+                name,
+                Variable {
+                    defined: true,
+                    slot: 0,
+                },
+            );
+        }
+    }
+
+    fn error(&mut self, token: &token_type::Token, code: ErrorCode, message: &str) {
+        self.errors.push(LoxError::resolve(
+            code,
+            token.span,
+            format!("{} at '{}'", message, token.lexeme),
+        ));
+    }
+}
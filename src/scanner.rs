@@ -1,14 +1,18 @@
-use crate::lox;
+use crate::error::{ErrorCode, LoxError, Span};
+use crate::symbol::Symbol;
 use crate::token_type::*;
 
 pub struct Scanner {
     view: String, // Current char(s) being scanned
     chars: std::iter::Peekable<std::vec::IntoIter<char>>,
     pub tokens: Vec<Token>,
-    pub errors: Vec<lox::Error>,
-    start: usize,
-    current: usize,
+    pub errors: Vec<LoxError>,
+    start: usize,   // byte offset of the token being scanned
+    current: usize, // byte offset of the next char
     line: usize,
+    column: usize,
+    start_line: usize,
+    start_column: usize,
 }
 
 impl Scanner {
@@ -19,18 +23,28 @@ impl Scanner {
         }
     }
 
-    pub fn scan_tokens(source: String) -> Result<Vec<Token>, Vec<lox::Error>> {
-        let mut scanner: Scanner = Self::new(source);
+    pub fn scan_tokens(source: String) -> Result<Vec<Token>, Vec<LoxError>> {
+        Self::scan_tokens_from(&source, 0)
+    }
+
+    /// Scans `source` from byte `from`, which must start a line, with spans
+    /// still pointing into the whole of `source`. A REPL passes everything
+    /// entered so far, so errors in earlier input can show the line they're on.
+    pub fn scan_tokens_from(source: &str, from: usize) -> Result<Vec<Token>, Vec<LoxError>> {
+        let mut scanner: Scanner = Self::new(source[from..].to_string());
+        scanner.start = from;
+        scanner.current = from;
+        scanner.line = 1 + source[..from].matches('\n').count();
         while scanner.chars.peek().is_some() {
             scanner.start = scanner.current;
+            scanner.start_line = scanner.line;
+            scanner.start_column = scanner.column;
             scanner.scan_token();
         }
-        scanner.tokens.push(Token {
-            r#type: TokenType::Eof,
-            lexeme: "".to_string(),
-            literal: None,
-            line: scanner.line,
-        });
+        scanner.start = scanner.current;
+        scanner.start_line = scanner.line;
+        scanner.start_column = scanner.column;
+        scanner.add_token(TokenType::Eof);
         if scanner.errors.is_empty() {
             return Ok(scanner.tokens);
         }
@@ -38,183 +52,102 @@ impl Scanner {
     }
 
     fn scan_token(&mut self) {
-        let c = self.chars.next().unwrap();
+        let Some(c) = self.advance() else {
+            return;
+        };
         self.view.push(c);
         match c {
-            '(' => self.tokens.push(Self::create_token(
-                TokenType::LeftParen,
-                &mut self.view,
-                self.line,
-            )),
-            ')' => self.tokens.push(Self::create_token(
-                TokenType::RightParen,
-                &mut self.view,
-                self.line,
-            )),
-            '{' => self.tokens.push(Self::create_token(
-                TokenType::LeftBrace,
-                &mut self.view,
-                self.line,
-            )),
-            '}' => self.tokens.push(Self::create_token(
-                TokenType::RightBrace,
-                &mut self.view,
-                self.line,
-            )),
-            ',' => self.tokens.push(Self::create_token(
-                TokenType::Comma,
-                &mut self.view,
-                self.line,
-            )),
-            '.' => self.tokens.push(Self::create_token(
-                TokenType::Dot,
-                &mut self.view,
-                self.line,
-            )),
-            '-' => self.tokens.push(Self::create_token(
-                TokenType::Minus,
-                &mut self.view,
-                self.line,
-            )),
-            '+' => self.tokens.push(Self::create_token(
-                TokenType::Plus,
-                &mut self.view,
-                self.line,
-            )),
-            ';' => self.tokens.push(Self::create_token(
-                TokenType::Semicolon,
-                &mut self.view,
-                self.line,
-            )),
-            '*' => self.tokens.push(Self::create_token(
-                TokenType::Star,
-                &mut self.view,
-                self.line,
-            )),
+            '(' => self.add_token(TokenType::LeftParen),
+            ')' => self.add_token(TokenType::RightParen),
+            '{' => self.add_token(TokenType::LeftBrace),
+            '}' => self.add_token(TokenType::RightBrace),
+            '[' => self.add_token(TokenType::LeftBracket),
+            ']' => self.add_token(TokenType::RightBracket),
+            ',' => self.add_token(TokenType::Comma),
+            ':' => self.add_token(TokenType::Colon),
+            '.' => self.add_token(TokenType::Dot),
+            '-' => self.add_token(TokenType::Minus),
+            '+' => self.add_token(TokenType::Plus),
+            ';' => self.add_token(TokenType::Semicolon),
+            '*' => self.add_token(TokenType::Star),
             '!' => match self.chars.peek() {
                 Some('=') => {
-                    self.view.push(self.chars.next().unwrap());
-                    self.tokens.push(Self::create_token(
-                        TokenType::BangEqual,
-                        &mut self.view,
-                        self.line,
-                    ))
+                    self.consume();
+                    self.add_token(TokenType::BangEqual)
                 }
-                _ => self.tokens.push(Self::create_token(
-                    TokenType::Bang,
-                    &mut self.view,
-                    self.line,
-                )),
+                _ => self.add_token(TokenType::Bang),
             },
             '=' => match self.chars.peek() {
                 Some('=') => {
-                    self.view.push(self.chars.next().unwrap());
-                    self.tokens.push(Self::create_token(
-                        TokenType::EqualEqual,
-                        &mut self.view,
-                        self.line,
-                    ))
+                    self.consume();
+                    self.add_token(TokenType::EqualEqual)
                 }
-                _ => self.tokens.push(Self::create_token(
-                    TokenType::Equal,
-                    &mut self.view,
-                    self.line,
-                )),
+                _ => self.add_token(TokenType::Equal),
             },
             '<' => match self.chars.peek() {
                 Some('=') => {
-                    self.view.push(self.chars.next().unwrap());
-                    self.tokens.push(Self::create_token(
-                        TokenType::LessEqual,
-                        &mut self.view,
-                        self.line,
-                    ))
+                    self.consume();
+                    self.add_token(TokenType::LessEqual)
                 }
-                _ => self.tokens.push(Self::create_token(
-                    TokenType::Less,
-                    &mut self.view,
-                    self.line,
-                )),
+                _ => self.add_token(TokenType::Less),
             },
             '>' => match self.chars.peek() {
                 Some('=') => {
-                    self.view.push(self.chars.next().unwrap());
-                    self.tokens.push(Self::create_token(
-                        TokenType::GreaterEqual,
-                        &mut self.view,
-                        self.line,
-                    ))
+                    self.consume();
+                    self.add_token(TokenType::GreaterEqual)
                 }
-                _ => self.tokens.push(Self::create_token(
-                    TokenType::Greater,
-                    &mut self.view,
-                    self.line,
-                )),
+                _ => self.add_token(TokenType::Greater),
             },
             '/' => match self.chars.peek() {
                 Some('/') => {
-                    self.chars.next();
-                    while self.chars.peek() != Some(&'\n') && self.chars.peek() != None {
-                        self.chars.next();
+                    self.advance();
+                    while self.chars.peek() != Some(&'\n') && self.chars.peek().is_some() {
+                        self.advance();
                     }
                 }
-                _ => self.tokens.push(Self::create_token(
-                    TokenType::Slash,
-                    &mut self.view,
-                    self.line,
-                )),
+                _ => self.add_token(TokenType::Slash),
             },
             '"' => match self.scan_string() {
-                Some(string) => self.tokens.push(Self::create_token_with_literal(
-                    TokenType::String,
-                    &mut self.view,
-                    self.line,
-                    Some(string),
+                Some(string) => self.add_token_with_literal(TokenType::String, Some(string)),
+                None => self.errors.push(LoxError::scan(
+                    ErrorCode::UnterminatedString,
+                    self.span(),
+                    "Unterminated string",
                 )),
-                None => self.errors.push(lox::Error {
-                    line: self.line,
-                    message: format!("Failed to parse string: {}", self.view),
-                }),
             },
             '0'..='9' => match self.scan_number() {
-                Ok(number) => self.tokens.push(Self::create_token_with_literal(
-                    TokenType::Number,
-                    &mut self.view,
-                    self.line,
-                    Some(number),
-                )),
+                Ok(number) => self.add_token_with_literal(TokenType::Number, Some(number)),
                 Err(e) => {
                     self.errors.push(e);
                 }
             },
             'a'..='z' | 'A'..='Z' | '_' => match self.scan_identifier() {
-                Ok(identifier) => {
-                    self.tokens
-                        .push(Self::create_token(identifier, &mut self.view, self.line))
-                }
+                Ok(identifier) => self.add_token(identifier),
                 Err(e) => {
                     self.errors.push(e);
                 }
             },
-            ' ' | '\r' | '\t' => {}
-            '\n' => self.line += 1,
+            ' ' | '\r' | '\t' | '\n' => {}
             other => {
-                self.errors.push(lox::Error {
-                    line: self.line,
-                    message: format!("Unexpected character: {}", other),
-                });
+                self.errors.push(LoxError::scan(
+                    ErrorCode::UnexpectedCharacter,
+                    self.span(),
+                    format!("Unexpected character: {}", other),
+                ));
             }
         }
         self.view = "".to_string();
     }
 
-    fn scan_identifier(&mut self) -> Result<TokenType, lox::Error> {
+    fn scan_identifier(&mut self) -> Result<TokenType, LoxError> {
         while Scanner::is_alphanumeric(self.chars.peek()) {
-            self.view.push(self.chars.next().unwrap())
+            self.consume();
         }
         let token = match self.view.as_str() {
             "and" => TokenType::And,
+            "break" => TokenType::Break,
             "class" => TokenType::Class,
+            "continue" => TokenType::Continue,
             "else" => TokenType::Else,
             "false" => TokenType::False,
             "for" => TokenType::For,
@@ -239,70 +172,91 @@ impl Scanner {
     }
 
     fn is_alpha(c: Option<&char>) -> bool {
-        match c {
-            Some(c) => match c {
-                'a'..='z' | 'A'..='Z' | '_' => true,
-                _ => false,
-            },
-            None => false,
-        }
+        matches!(c, Some('a'..='z' | 'A'..='Z' | '_'))
     }
 
-    fn scan_number(&mut self) -> Result<Literal, lox::Error> {
-        let mut number = self.view.clone();
+    fn scan_number(&mut self) -> Result<Literal, LoxError> {
         while Scanner::is_digit(self.chars.peek()) {
-            number.push(self.chars.next().unwrap());
+            self.consume();
         }
         if self.chars.peek() == Some(&'.') {
-            number.push(self.chars.next().unwrap());
+            self.consume();
             while Scanner::is_digit(self.chars.peek()) {
-                number.push(self.chars.next().unwrap());
+                self.consume();
             }
         }
-        match number.parse() {
+        match self.view.parse() {
             Ok(number) => Ok(Literal::Number(number)),
-            Err(e) => Err(lox::Error {
-                line: self.line,
-                message: format!("Failed to parse number: {}, {}", self.view, e),
-            }),
+            Err(e) => Err(LoxError::scan(
+                ErrorCode::InvalidNumber,
+                self.span(),
+                format!("Failed to parse number: {}, {}", self.view, e),
+            )),
         }
     }
 
     fn is_digit(c: Option<&char>) -> bool {
         match c {
-            Some(c) => ('0'..='9').contains(c),
+            Some(c) => c.is_ascii_digit(),
             None => false,
         }
     }
 
+    /// Scans the rest of a string literal, or returns None if the source
+    /// ends before the closing quote.
     fn scan_string(&mut self) -> Option<Literal> {
         let mut string = "".to_owned();
-        while self.chars.peek() != Some(&'"') {
-            let c = self.chars.next().unwrap();
-            if c == '\n' {
-                self.line += 1;
+        loop {
+            let c = self.advance()?;
+            self.view.push(c);
+            if c == '"' {
+                return Some(Literal::String(Symbol::intern(&string)));
             }
             string.push(c);
         }
-        self.view.push(self.chars.next().unwrap());
-        Some(Literal::String(string))
     }
 
-    fn create_token(r#type: TokenType, view: &mut String, line: usize) -> Token {
-        Self::create_token_with_literal(r#type, view, line, None)
+    fn add_token(&mut self, r#type: TokenType) {
+        self.add_token_with_literal(r#type, None)
     }
 
-    fn create_token_with_literal(
-        r#type: TokenType,
-        view: &mut String,
-        line: usize,
-        literal: Option<Literal>,
-    ) -> Token {
-        Token {
+    fn add_token_with_literal(&mut self, r#type: TokenType, literal: Option<Literal>) {
+        self.tokens.push(Token {
             r#type,
-            lexeme: view.to_owned(),
+            lexeme: Symbol::intern(&self.view),
             literal,
-            line,
+            span: self.span(),
+        });
+    }
+
+    /// Consumes the next char, keeping the byte offset, line and column of
+    /// the scan position in sync with it.
+    fn advance(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        self.current += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    /// Consumes the next char as part of the current lexeme.
+    fn consume(&mut self) {
+        if let Some(c) = self.advance() {
+            self.view.push(c);
+        }
+    }
+
+    /// The span from the start of the current token to the scan position.
+    fn span(&self) -> Span {
+        Span {
+            line: self.start_line,
+            column: self.start_column,
+            start: self.start,
+            end: self.current,
         }
     }
 }
@@ -322,6 +276,9 @@ impl Default for Scanner {
             start: 0,
             current: 0,
             line: 1,
+            column: 1,
+            start_line: 1,
+            start_column: 1,
         }
     }
 }
@@ -330,6 +287,6 @@ impl Iterator for Scanner {
     type Item = char;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.chars.next()
+        self.advance()
     }
 }
@@ -1,5 +1,7 @@
+use crate::error::Span;
 use crate::expr;
 use crate::token_type;
+use std::rc::Rc;
 
 pub enum Stmt {
     Expr(Expr),
@@ -8,32 +10,132 @@ pub enum Stmt {
     Block(Block),
     If(If),
     While(While),
+    Function(Rc<Function>),
+    Return(Return),
+    Class(Class),
+    Break(Break),
+    Continue(Continue),
+}
+
+impl Stmt {
+    /// The source range the statement was parsed from.
+    pub fn span(&self) -> Span {
+        match self {
+            Stmt::Expr(x) => x.span,
+            Stmt::Print(x) => x.span,
+            Stmt::VarDec(x) => x.span,
+            Stmt::Block(x) => x.span,
+            Stmt::If(x) => x.span,
+            Stmt::While(x) => x.span,
+            Stmt::Function(x) => x.span,
+            Stmt::Return(x) => x.span,
+            Stmt::Class(x) => x.span,
+            Stmt::Break(x) => x.span,
+            Stmt::Continue(x) => x.span,
+        }
+    }
 }
 
 pub struct Expr {
     pub expression: expr::Expr,
+    pub span: Span,
 }
 
 pub struct Print {
     pub expression: expr::Expr,
+    pub span: Span,
 }
 
 pub struct VarDec {
     pub name: token_type::Token,
     pub expression: Option<expr::Expr>,
+    pub span: Span,
 }
 
 pub struct Block {
     pub statements: Vec<Stmt>,
+    pub span: Span,
+}
+impl Block {
+    /// Whether the block declares any variables, functions or classes of its
+    /// own. Only blocks that do get a scope, so a loop body that just updates
+    /// outer variables doesn't create an environment per iteration.
+    pub fn has_scope(&self) -> bool {
+        self.statements
+            .iter()
+            .any(|stmt| matches!(stmt, Stmt::VarDec(_) | Stmt::Function(_) | Stmt::Class(_)))
+    }
 }
 
 pub struct If {
     pub condition: expr::Expr,
     pub then_branch: Box<Stmt>,
     pub else_branch: Option<Box<Stmt>>,
+    pub span: Span,
+}
+impl If {
+    /// Splits an `else if` ladder into its `if`s, in order, and the final
+    /// `else` branch. A ladder nests as deeply as it is long, so passes over
+    /// the tree follow it in a loop instead of recursing.
+    pub fn ladder(&self) -> (Vec<&If>, Option<&Stmt>) {
+        let mut ifs = vec![self];
+        let mut last = self;
+        loop {
+            match last.else_branch.as_deref() {
+                Some(Stmt::If(next)) => {
+                    ifs.push(next);
+                    last = next;
+                }
+                otherwise => return (ifs, otherwise),
+            }
+        }
+    }
+}
+impl Drop for If {
+    fn drop(&mut self) {
+        let mut next = self.else_branch.take();
+        while let Some(branch) = next {
+            next = match *branch {
+                Stmt::If(mut if_stmt) => if_stmt.else_branch.take(),
+                _ => None,
+            };
+        }
+    }
 }
 
 pub struct While {
     pub condition: expr::Expr,
     pub body: Box<Stmt>,
+    pub increment: Option<expr::Expr>, // from a desugared `for`, runs even after `continue`
+    pub span: Span,
+}
+
+pub struct Function {
+    pub name: token_type::Token,
+    pub params: Vec<token_type::Token>,
+    pub body: Vec<Stmt>,
+    pub span: Span,
+}
+
+pub struct Return {
+    pub keyword: token_type::Token,
+    pub value: Option<expr::Expr>,
+    pub span: Span,
+}
+
+pub struct Class {
+    pub name: token_type::Token,
+    pub superclass: Option<expr::Variable>,
+    pub methods: Vec<Rc<Function>>,
+    pub span: Span,
+}
+
+pub struct Break {
+    pub keyword: token_type::Token,
+    pub span: Span,
+}
+
+pub struct Continue {
+    pub keyword: token_type::Token,
+    pub span: Span,
 }
@@ -0,0 +1,109 @@
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::{cell::RefCell, fmt, ops::Deref, rc::Rc};
+
+/// How many strings may be interned before the first prune.
+const INITIAL_THRESHOLD: usize = 1024;
+
+thread_local! {
+    static INTERNED: RefCell<Interned> = RefCell::new(Interned {
+        strings: HashSet::new(),
+        next_prune: INITIAL_THRESHOLD,
+    });
+}
+
+/// The strings symbols point to. A REPL or an embedder evaluating code for a
+/// long time keeps interning new names, so strings no symbol uses any more
+/// are pruned once the set has doubled since the last prune.
+struct Interned {
+    strings: HashSet<Rc<str>>,
+    next_prune: usize, // prune once this many strings are interned
+}
+
+/// Forgets every interned string that only the interner refers to, and
+/// returns how many there were. Interning the same string again later gives
+/// a new symbol, which is fine as no old one is left to compare it with.
+pub fn prune() -> usize {
+    INTERNED.with(|interned| {
+        let mut interned = interned.borrow_mut();
+        let before = interned.strings.len();
+        interned
+            .strings
+            .retain(|string| Rc::strong_count(string) > 1);
+        let live = interned.strings.len();
+        interned.next_prune = INITIAL_THRESHOLD.max(live * 2);
+        before - live
+    })
+}
+
+/// An interned string, used for identifiers and string constants. Equal
+/// strings share one allocation, so symbols clone without copying and compare
+/// and hash by pointer instead of by contents.
+#[derive(Clone)]
+pub struct Symbol(Rc<str>);
+impl Symbol {
+    pub fn intern(name: &str) -> Symbol {
+        let due = INTERNED.with(|interned| {
+            let interned = interned.borrow();
+            interned.strings.len() >= interned.next_prune
+        });
+        if due {
+            prune();
+        }
+        INTERNED.with(|interned| {
+            if let Some(existing) = interned.borrow().strings.get(name) {
+                return Symbol(existing.clone());
+            }
+            let name: Rc<str> = Rc::from(name);
+            interned.borrow_mut().strings.insert(name.clone());
+            Symbol(name)
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The interned string, e.g. to use as a `Value::String` without copying.
+    pub fn to_rc(&self) -> Rc<str> {
+        self.0.clone()
+    }
+}
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+impl Eq for Symbol {}
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (Rc::as_ptr(&self.0) as *const () as usize).hash(state);
+    }
+}
+impl Deref for Symbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
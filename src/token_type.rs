@@ -1,12 +1,18 @@
+use crate::error::Span;
+use crate::symbol::Symbol;
+use crate::value;
 use std::fmt;
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum TokenType {
     // Single and double char tokens
     LeftParen,
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
+    Colon,
     Dot,
     Minus,
     Plus,
@@ -27,7 +33,9 @@ pub enum TokenType {
     Number,
     // Keywords
     And,
+    Break,
     Class,
+    Continue,
     Else,
     False,
     Fun,
@@ -54,12 +62,12 @@ impl fmt::Display for TokenType {
 #[derive(Debug, Clone)]
 pub enum Literal {
     Number(f64),
-    String(String),
+    String(Symbol),
 }
 impl fmt::Display for Literal {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Literal::Number(x) => write!(f, "{}", x),
+            Literal::Number(x) => write!(f, "{}", value::format_number(*x)),
             Literal::String(x) => write!(f, "{}", x),
         }
     }
@@ -68,9 +76,9 @@ impl fmt::Display for Literal {
 #[derive(Clone)]
 pub struct Token {
     pub r#type: TokenType,
-    pub lexeme: String,
+    pub lexeme: Symbol,
     pub literal: Option<Literal>, // could be either a string or a number
-    pub line: usize,
+    pub span: Span,
 }
 impl fmt::Display for Token {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
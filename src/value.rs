@@ -0,0 +1,419 @@
+use crate::callable::LoxFunction;
+use crate::class::{LoxClass, LoxInstance};
+use crate::error::{ErrorCode, LoxError, Span};
+use crate::expr;
+use crate::gc::{self, Trace};
+use crate::native::NativeFunction;
+use crate::vm::{BoundMethod, Closure};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+use std::ops::{Deref, DerefMut};
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+};
+
+thread_local! {
+    /// Collections currently being displayed, innermost last.
+    static DISPLAYING: RefCell<Vec<usize>> = const { RefCell::new(vec![]) };
+    /// Values waiting to be dropped by `release`.
+    static RELEASED: RefCell<Vec<Value>> = const { RefCell::new(vec![]) };
+    static RELEASING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// A runtime value. Unlike `expr::Literal`, which only holds constants written
+/// in the source, values may also be heap objects created while running.
+#[derive(Clone)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    String(Rc<str>),
+    Function(Rc<LoxFunction>),
+    Closure(Rc<Closure>), // a function compiled for the VM
+    BoundMethod(Rc<BoundMethod>),
+    Native(Rc<NativeFunction>),
+    Class(Rc<LoxClass>),
+    Instance(Rc<RefCell<LoxInstance>>),
+    List(Rc<RefCell<List>>),
+    Map(Rc<RefCell<Map>>),
+}
+impl Value {
+    /// A new list, tracked by the garbage collector. Natives should create
+    /// lists with this rather than `Value::List` so cycles through them are
+    /// freed.
+    pub fn list(elements: Vec<Value>) -> Value {
+        Value::List(gc::alloc(RefCell::new(List(elements))))
+    }
+
+    /// A new map, tracked by the garbage collector like `Value::list`.
+    pub fn map(entries: BTreeMap<MapKey, Value>) -> Value {
+        Value::Map(gc::alloc(RefCell::new(Map(entries))))
+    }
+
+    /// Calls `visit` with the heap object this value refers to, if any.
+    pub fn trace(&self, visit: &mut dyn FnMut(usize)) {
+        if let Some(id) = self.object_id() {
+            visit(id);
+        }
+    }
+
+    /// Identifies the object a value refers to, or None for values compared
+    /// by contents. Every new kind of object should be added here, which is
+    /// all it takes for it to have identity equality.
+    fn object_id(&self) -> Option<usize> {
+        match self {
+            Value::Function(x) => Some(gc::id(x)),
+            Value::Closure(x) => Some(gc::id(x)),
+            Value::BoundMethod(x) => Some(gc::id(x)),
+            Value::Native(x) => Some(gc::id(x)),
+            Value::Class(x) => Some(gc::id(x)),
+            Value::Instance(x) => Some(gc::id(x)),
+            Value::List(x) => Some(gc::id(x)),
+            Value::Map(x) => Some(gc::id(x)),
+            Value::Nil | Value::Bool(_) | Value::Number(_) | Value::String(_) => None,
+        }
+    }
+
+    /// The value as the REPL shows it, with strings quoted.
+    pub fn repr(&self) -> Repr<'_> {
+        Repr(self)
+    }
+
+    /// `nil` and `false` are falsey, everything else is truthy.
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Value::Nil => false,
+            Value::Bool(b) => *b,
+            _ => true,
+        }
+    }
+}
+impl From<&expr::Literal> for Value {
+    fn from(literal: &expr::Literal) -> Self {
+        match literal {
+            expr::Literal::Number(x) => Value::Number(*x),
+            expr::Literal::String(x) => Value::String(x.to_rc()),
+            expr::Literal::Bool(x) => Value::Bool(*x),
+            expr::Literal::Nil => Value::Nil,
+        }
+    }
+}
+/// Lox's `==`: values of different types are never equal, nil equals nil,
+/// booleans, numbers and strings compare by value, and objects are only
+/// equal to themselves.
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(x), Value::Bool(y)) => x == y,
+            (Value::Number(x), Value::Number(y)) => x == y,
+            (Value::String(x), Value::String(y)) => x == y,
+            _ => match (self.object_id(), other.object_id()) {
+                (Some(x), Some(y)) => x == y,
+                _ => false,
+            },
+        }
+    }
+}
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.repr())
+    }
+}
+/// How `print` shows a value.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Bool(x) => write!(f, "{}", x),
+            Value::Number(x) => write!(f, "{}", format_number(*x)),
+            Value::String(x) => write!(f, "{}", x),
+            Value::Function(x) => write!(f, "<fn {}>", x.declaration.name.lexeme),
+            Value::Closure(x) => write!(f, "{}", x.function),
+            Value::BoundMethod(x) => write!(f, "{}", x.method.function),
+            Value::Native(x) => write!(f, "<native fn {}>", x.name),
+            Value::Class(x) => write!(f, "{}", x.name),
+            Value::Instance(x) => write!(f, "{} instance", x.borrow().class.name),
+            Value::List(x) => guard_cycle(f, Rc::as_ptr(x) as usize, "[...]", |f| {
+                write_list(f, &x.borrow())
+            }),
+            Value::Map(x) => guard_cycle(f, Rc::as_ptr(x) as usize, "{...}", |f| {
+                write_map(f, &x.borrow())
+            }),
+        }
+    }
+}
+
+/// Drops `values` without recursing into the objects they refer to. Objects
+/// that hold values hand them over here when they are dropped, so freeing a
+/// long chain of objects, like a linked list built in a loop, takes a loop
+/// instead of one nested drop per object, which would overflow the stack.
+pub fn release(values: impl IntoIterator<Item = Value>) {
+    // Values without objects drop right away; they can't start a chain
+    RELEASED.with(|released| {
+        let objects = values
+            .into_iter()
+            .filter(|value| value.object_id().is_some());
+        released.borrow_mut().extend(objects)
+    });
+    // Only the outermost call drops anything; nested ones just queue
+    if RELEASING.with(|releasing| releasing.replace(true)) {
+        return;
+    }
+    while let Some(value) = RELEASED.with(|released| released.borrow_mut().pop()) {
+        drop(value);
+    }
+    RELEASING.with(|releasing| releasing.set(false));
+}
+
+/// The elements of a list.
+#[derive(Default)]
+pub struct List(pub Vec<Value>);
+impl Deref for List {
+    type Target = Vec<Value>;
+
+    fn deref(&self) -> &Vec<Value> {
+        &self.0
+    }
+}
+impl DerefMut for List {
+    fn deref_mut(&mut self) -> &mut Vec<Value> {
+        &mut self.0
+    }
+}
+impl Drop for List {
+    fn drop(&mut self) {
+        release(std::mem::take(&mut self.0));
+    }
+}
+
+/// The entries of a map.
+#[derive(Default)]
+pub struct Map(pub BTreeMap<MapKey, Value>);
+impl Deref for Map {
+    type Target = BTreeMap<MapKey, Value>;
+
+    fn deref(&self) -> &BTreeMap<MapKey, Value> {
+        &self.0
+    }
+}
+impl DerefMut for Map {
+    fn deref_mut(&mut self) -> &mut BTreeMap<MapKey, Value> {
+        &mut self.0
+    }
+}
+impl Drop for Map {
+    fn drop(&mut self) {
+        release(std::mem::take(&mut self.0).into_values());
+    }
+}
+
+impl Trace for RefCell<List> {
+    fn trace(&self, visit: &mut dyn FnMut(usize)) {
+        if let Ok(elements) = self.try_borrow() {
+            elements.iter().for_each(|value| value.trace(visit));
+        }
+    }
+
+    fn clear(&self) {
+        if let Ok(mut elements) = self.try_borrow_mut() {
+            elements.clear();
+        }
+    }
+}
+impl Trace for RefCell<Map> {
+    fn trace(&self, visit: &mut dyn FnMut(usize)) {
+        if let Ok(entries) = self.try_borrow() {
+            entries.values().for_each(|value| value.trace(visit));
+        }
+    }
+
+    fn clear(&self) {
+        if let Ok(mut entries) = self.try_borrow_mut() {
+            entries.clear();
+        }
+    }
+}
+
+/// Shows a value the way the REPL echoes it: like `print`, except that
+/// strings are quoted and escaped, so `"1"` and `1` can be told apart.
+pub struct Repr<'a>(&'a Value);
+impl fmt::Display for Repr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Value::String(x) => write!(f, "{}", Quoted(x)),
+            value => write!(f, "{}", value),
+        }
+    }
+}
+
+/// Shows a string in double quotes, with quotes, backslashes and control
+/// characters escaped.
+pub struct Quoted<'a>(pub &'a str);
+impl fmt::Display for Quoted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_char('"')?;
+        for c in self.0.chars() {
+            match c {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                '\n' => f.write_str("\\n")?,
+                '\r' => f.write_str("\\r")?,
+                '\t' => f.write_str("\\t")?,
+                c if c.is_control() => write!(f, "\\u{{{:x}}}", c as u32)?,
+                c => f.write_char(c)?,
+            }
+        }
+        f.write_char('"')
+    }
+}
+
+/// Formats a number the same way on every backend: integral values without
+/// a fractional part, others in the shortest form that reads back as the same
+/// number, and the special values spelled as in jlox.
+pub fn format_number(x: f64) -> String {
+    match x {
+        x if x.is_nan() => "NaN".to_string(),
+        f64::INFINITY => "Infinity".to_string(),
+        f64::NEG_INFINITY => "-Infinity".to_string(),
+        x => format!("{}", x),
+    }
+}
+
+/// Writes a collection with `write`, or `placeholder` if the collection
+/// (identified by address) is already being written further up, so cycles
+/// terminate.
+fn guard_cycle(
+    f: &mut fmt::Formatter,
+    id: usize,
+    placeholder: &str,
+    write: impl FnOnce(&mut fmt::Formatter) -> fmt::Result,
+) -> fmt::Result {
+    if DISPLAYING.with(|displaying| displaying.borrow().contains(&id)) {
+        return write!(f, "{}", placeholder);
+    }
+    DISPLAYING.with(|displaying| displaying.borrow_mut().push(id));
+    let result = write(f);
+    DISPLAYING.with(|displaying| displaying.borrow_mut().pop());
+    result
+}
+
+fn write_list(f: &mut fmt::Formatter, elements: &[Value]) -> fmt::Result {
+    write!(f, "[")?;
+    for (i, element) in elements.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", element.repr())?;
+    }
+    write!(f, "]")
+}
+
+fn write_map(f: &mut fmt::Formatter, entries: &BTreeMap<MapKey, Value>) -> fmt::Result {
+    write!(f, "{{")?;
+    for (i, (key, value)) in entries.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}: {}", Value::from(key.clone()).repr(), value.repr())?;
+    }
+    write!(f, "}}")
+}
+
+/// A value usable as a map key. Maps iterate in key order: booleans, then
+/// numbers, then strings.
+#[derive(Debug, Clone)]
+pub enum MapKey {
+    Bool(bool),
+    Number(f64), // never NaN, and -0 is stored as 0
+    String(Rc<str>),
+}
+impl TryFrom<&Value> for MapKey {
+    type Error = String;
+
+    fn try_from(value: &Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Bool(x) => Ok(MapKey::Bool(*x)),
+            Value::Number(x) if x.is_nan() => Err("Map keys can't be NaN".to_string()),
+            Value::Number(x) => Ok(MapKey::Number(x + 0.0)),
+            Value::String(x) => Ok(MapKey::String(x.clone())),
+            _ => Err("Map keys must be strings, numbers or booleans".to_string()),
+        }
+    }
+}
+impl From<MapKey> for Value {
+    fn from(key: MapKey) -> Self {
+        match key {
+            MapKey::Bool(x) => Value::Bool(x),
+            MapKey::Number(x) => Value::Number(x),
+            MapKey::String(x) => Value::String(x),
+        }
+    }
+}
+impl Ord for MapKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (MapKey::Bool(x), MapKey::Bool(y)) => x.cmp(y),
+            (MapKey::Number(x), MapKey::Number(y)) => x.total_cmp(y),
+            (MapKey::String(x), MapKey::String(y)) => x.cmp(y),
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+impl PartialOrd for MapKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl PartialEq for MapKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for MapKey {}
+impl MapKey {
+    fn rank(&self) -> u8 {
+        match self {
+            MapKey::Bool(_) => 0,
+            MapKey::Number(_) => 1,
+            MapKey::String(_) => 2,
+        }
+    }
+}
+
+/// Converts an index into a map key, reporting an unusable key at `span`.
+pub fn map_key(key: &Value, span: Span) -> Result<MapKey, LoxError> {
+    MapKey::try_from(key).map_err(|message| LoxError::runtime(ErrorCode::InvalidKey, span, message))
+}
+
+/// Checks that `index` is a whole number within a list of length `len`.
+pub fn list_index(index: &Value, len: usize, span: Span) -> Result<usize, LoxError> {
+    let i = match index {
+        Value::Number(i) if i.fract() == 0.0 => *i,
+        _ => {
+            return Err(LoxError::runtime(
+                ErrorCode::InvalidIndex,
+                span,
+                "List index must be a whole number",
+            ))
+        }
+    };
+    if i < 0.0 || i >= len as f64 {
+        return Err(LoxError::runtime(
+            ErrorCode::IndexOutOfBounds,
+            span,
+            format!("Index {} out of bounds for list of length {}", i, len),
+        ));
+    }
+    Ok(i as usize)
+}
+
+/// Joins two strings into a new one, for `+`.
+pub fn concat(x: &str, y: &str) -> Rc<str> {
+    let mut joined = String::with_capacity(x.len() + y.len());
+    joined.push_str(x);
+    joined.push_str(y);
+    Rc::from(joined)
+}
@@ -0,0 +1,656 @@
+use crate::chunk::{Constant, FunctionProto, OpCode};
+use crate::class::{LoxClass, LoxInstance, Method};
+use crate::error::{ErrorCode, LoxError, Span};
+use crate::gc::{self, Trace};
+use crate::native::{self, NativeFunction};
+use crate::symbol::Symbol;
+use crate::value::{self, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::{cell::RefCell, io, rc::Rc};
+
+/// How many Lox calls may be active at once, matching the tree-walker.
+const MAX_CALL_DEPTH: usize = 256;
+
+/// A compiled function together with the variables it captured.
+pub struct Closure {
+    pub function: Rc<FunctionProto>,
+    pub upvalues: Vec<Rc<RefCell<Upvalue>>>,
+}
+
+/// A captured variable. It points into the stack while the variable's scope
+/// is active, and holds the value itself once the scope has exited.
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+/// A method closure paired with the instance it was accessed on.
+pub struct BoundMethod {
+    pub receiver: Rc<RefCell<LoxInstance>>,
+    pub method: Rc<Closure>,
+}
+
+impl Trace for Closure {
+    fn trace(&self, visit: &mut dyn FnMut(usize)) {
+        self.upvalues
+            .iter()
+            .for_each(|upvalue| visit(gc::id(upvalue)));
+    }
+}
+impl Trace for RefCell<Upvalue> {
+    fn trace(&self, visit: &mut dyn FnMut(usize)) {
+        if let Ok(upvalue) = self.try_borrow() {
+            if let Upvalue::Closed(value) = &*upvalue {
+                value.trace(visit);
+            }
+        }
+    }
+
+    fn clear(&self) {
+        if let Ok(mut upvalue) = self.try_borrow_mut() {
+            if let Upvalue::Closed(_) = *upvalue {
+                *upvalue = Upvalue::Closed(Value::Nil);
+            }
+        }
+    }
+}
+impl Drop for Upvalue {
+    fn drop(&mut self) {
+        if let Upvalue::Closed(value) = self {
+            value::release([std::mem::replace(value, Value::Nil)]);
+        }
+    }
+}
+impl Trace for BoundMethod {
+    fn trace(&self, visit: &mut dyn FnMut(usize)) {
+        visit(gc::id(&self.receiver));
+        visit(gc::id(&self.method));
+    }
+}
+
+struct CallFrame {
+    closure: Rc<Closure>,
+    ip: usize,
+    base: usize, // stack index of slot 0
+}
+
+/// Stack-based bytecode VM, the alternative to the tree-walking
+/// `Interpreter`. Like the interpreter, it keeps its globals between runs.
+pub struct Vm {
+    globals: HashMap<Symbol, Value>,
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    output: Box<dyn io::Write>, // where `print` writes, stdout unless replaced
+    step_limit: Option<usize>,  // instructions one `interpret` may run
+    steps_left: usize,
+}
+impl Vm {
+    pub fn new() -> Self {
+        let mut vm = Vm {
+            globals: HashMap::new(),
+            stack: vec![],
+            frames: vec![],
+            open_upvalues: vec![],
+            output: Box::new(io::stdout()),
+            step_limit: None,
+            steps_left: 0,
+        };
+        for (name, arity, function) in native::BUILTINS {
+            vm.define_native(name, arity, function);
+        }
+        vm
+    }
+
+    /// Redirects `print` output. Returns the previous sink.
+    pub fn set_output(&mut self, output: Box<dyn io::Write>) -> Box<dyn io::Write> {
+        std::mem::replace(&mut self.output, output)
+    }
+
+    /// Makes `interpret` fail once a script has run `limit` instructions,
+    /// e.g. to run untrusted bytecode that may loop forever. `None` removes
+    /// the limit.
+    pub fn set_step_limit(&mut self, limit: Option<usize>) {
+        self.step_limit = limit;
+    }
+
+    /// Exposes a Rust function to scripts as a global named `name`.
+    pub fn define_native<F>(&mut self, name: &str, arity: usize, function: F)
+    where
+        F: Fn(&[Value]) -> Result<Value, String> + 'static,
+    {
+        let native = NativeFunction {
+            name: name.to_string(),
+            arity,
+            function: Box::new(function),
+        };
+        self.globals
+            .insert(Symbol::intern(name), Value::Native(Rc::new(native)));
+    }
+
+    /// Runs a compiled script and returns the value it returns.
+    pub fn interpret(&mut self, script: Rc<FunctionProto>) -> Result<Value, LoxError> {
+        let closure = gc::alloc(Closure {
+            function: script,
+            upvalues: vec![],
+        });
+        self.stack.push(Value::Closure(closure.clone()));
+        self.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            base: self.stack.len() - 1,
+        });
+        self.steps_left = self.step_limit.unwrap_or(usize::MAX);
+        let result = self.run();
+        if result.is_err() {
+            // Closures that escaped keep the values they captured
+            self.close_upvalues(0);
+            self.stack.clear();
+            self.frames.clear();
+            self.open_upvalues.clear();
+        }
+        result
+    }
+
+    fn run(&mut self) -> Result<Value, LoxError> {
+        // The function of the innermost frame, refreshed whenever it changes
+        let mut function = self.frame().closure.function.clone();
+        loop {
+            let byte = self.read_byte();
+            if self.steps_left == 0 {
+                return Err(self.error(ErrorCode::StepLimitExceeded, "Step limit exceeded"));
+            }
+            self.steps_left -= 1;
+            let op = OpCode::try_from(byte)
+                .unwrap_or_else(|byte| panic!("Compiler emitted an invalid opcode: {}", byte));
+            match op {
+                OpCode::Constant => {
+                    let index = self.read_u16();
+                    let value = match &function.chunk.constants[index as usize] {
+                        Constant::Number(x) => Value::Number(*x),
+                        Constant::String(x) => Value::String(x.to_rc()),
+                        Constant::Function(_) => {
+                            unreachable!("Compiler emitted a function as a plain constant")
+                        }
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::Nil => self.stack.push(Value::Nil),
+                OpCode::True => self.stack.push(Value::Bool(true)),
+                OpCode::False => self.stack.push(Value::Bool(false)),
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::GetLocal => {
+                    let slot = self.frame().base + self.read_byte() as usize;
+                    self.stack.push(self.stack[slot].clone());
+                }
+                OpCode::SetLocal => {
+                    let slot = self.frame().base + self.read_byte() as usize;
+                    self.stack[slot] = self.peek(0).clone();
+                }
+                OpCode::GetGlobal => {
+                    let name = string(&function, self.read_u16());
+                    match self.globals.get(name) {
+                        Some(value) => self.stack.push(value.clone()),
+                        None => return Err(self.undefined(name)),
+                    }
+                }
+                OpCode::DefineGlobal => {
+                    let name = string(&function, self.read_u16());
+                    let value = self.pop();
+                    self.globals.insert(name.clone(), value);
+                }
+                OpCode::SetGlobal => {
+                    let name = string(&function, self.read_u16());
+                    let value = self.peek(0).clone();
+                    match self.globals.get_mut(name) {
+                        Some(slot) => *slot = value,
+                        None => return Err(self.undefined(name)),
+                    }
+                }
+                OpCode::GetUpvalue => {
+                    let index = self.read_byte() as usize;
+                    let upvalue = self.frame().closure.upvalues[index].clone();
+                    let value = match &*upvalue.borrow() {
+                        Upvalue::Open(slot) => self.stack[*slot].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::SetUpvalue => {
+                    let index = self.read_byte() as usize;
+                    let upvalue = self.frame().closure.upvalues[index].clone();
+                    let value = self.peek(0).clone();
+                    let mut upvalue = upvalue.borrow_mut();
+                    match &mut *upvalue {
+                        Upvalue::Open(slot) => self.stack[*slot] = value,
+                        Upvalue::Closed(closed) => *closed = value,
+                    }
+                }
+                OpCode::GetProperty => {
+                    let name = string(&function, self.read_u16());
+                    let value = match self.pop() {
+                        Value::Instance(instance) => {
+                            LoxInstance::get(&instance, name, self.span())?
+                        }
+                        _ => {
+                            return Err(self
+                                .error(ErrorCode::NotAnInstance, "Only instances have properties"))
+                        }
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::SetProperty => {
+                    let name = string(&function, self.read_u16());
+                    let value = self.pop();
+                    match self.pop() {
+                        Value::Instance(instance) => instance.borrow_mut().set(name, value.clone()),
+                        _ => {
+                            return Err(
+                                self.error(ErrorCode::NotAnInstance, "Only instances have fields")
+                            )
+                        }
+                    }
+                    self.stack.push(value);
+                }
+                OpCode::GetSuper => {
+                    let name = string(&function, self.read_u16());
+                    let superclass = self.pop();
+                    let receiver = self.pop();
+                    let method = match (&superclass, receiver) {
+                        (Value::Class(class), Value::Instance(instance)) => {
+                            class.find_method(name).map(|method| method.bind(instance))
+                        }
+                        _ => None,
+                    };
+                    match method {
+                        Some(method) => self.stack.push(method),
+                        None => {
+                            return Err(self.error(
+                                ErrorCode::UndefinedProperty,
+                                &format!("Undefined property '{}'", name),
+                            ))
+                        }
+                    }
+                }
+                OpCode::Equal
+                | OpCode::NotEqual
+                | OpCode::Greater
+                | OpCode::GreaterEqual
+                | OpCode::Less
+                | OpCode::LessEqual
+                | OpCode::Add
+                | OpCode::Subtract
+                | OpCode::Multiply
+                | OpCode::Divide => self.binary(op)?,
+                OpCode::Not => {
+                    let value = self.pop();
+                    self.stack.push(Value::Bool(!value.is_truthy()));
+                }
+                OpCode::Negate => match self.pop() {
+                    Value::Number(x) => self.stack.push(Value::Number(-x)),
+                    _ => {
+                        return Err(
+                            self.error(ErrorCode::InvalidOperand, "Can only negate a Number")
+                        )
+                    }
+                },
+                OpCode::Print => {
+                    let value = self.pop();
+                    writeln!(self.output, "{}", value).map_err(|e| {
+                        LoxError::runtime(
+                            ErrorCode::Io,
+                            Span::default(),
+                            format!("Failed to write output: {}", e),
+                        )
+                    })?;
+                }
+                OpCode::Jump => {
+                    let offset = self.read_u16() as usize;
+                    self.frame_mut().ip += offset;
+                }
+                OpCode::JumpIfFalse => {
+                    let offset = self.read_u16() as usize;
+                    if !self.peek(0).is_truthy() {
+                        self.frame_mut().ip += offset;
+                    }
+                }
+                OpCode::Loop => {
+                    let offset = self.read_u16() as usize;
+                    self.frame_mut().ip -= offset;
+                }
+                OpCode::Call => {
+                    let count = self.read_byte() as usize;
+                    self.call_value(count)?;
+                    function = self.frame().closure.function.clone();
+                }
+                OpCode::Closure => {
+                    let index = self.read_u16();
+                    let Constant::Function(proto) = &function.chunk.constants[index as usize]
+                    else {
+                        panic!("Closure operand is not a function");
+                    };
+                    let mut upvalues = Vec::with_capacity(proto.upvalue_count);
+                    for _ in 0..proto.upvalue_count {
+                        let is_local = self.read_byte() == 1;
+                        let index = self.read_byte() as usize;
+                        let upvalue = match is_local {
+                            true => self.capture_upvalue(self.frame().base + index),
+                            false => self.frame().closure.upvalues[index].clone(),
+                        };
+                        upvalues.push(upvalue);
+                    }
+                    self.stack.push(Value::Closure(gc::alloc(Closure {
+                        function: proto.clone(),
+                        upvalues,
+                    })));
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                OpCode::Return => {
+                    let result = self.pop();
+                    let frame = self.frames.pop().expect("returned without a call frame");
+                    self.close_upvalues(frame.base);
+                    self.stack.truncate(frame.base);
+                    if self.frames.is_empty() {
+                        return Ok(result);
+                    }
+                    self.stack.push(result);
+                    function = self.frame().closure.function.clone();
+                }
+                OpCode::Class => self.class(&function)?,
+                OpCode::List => {
+                    let count = self.read_u16() as usize;
+                    let elements = self.stack.split_off(self.stack.len() - count);
+                    self.stack.push(Value::list(elements));
+                }
+                OpCode::Map => self.stack.push(Value::map(BTreeMap::new())),
+                OpCode::MapEntry => {
+                    let value = self.pop();
+                    let key = value::map_key(&self.pop(), self.span())?;
+                    if let Value::Map(map) = self.peek(0) {
+                        map.borrow_mut().insert(key, value);
+                    }
+                }
+                OpCode::GetIndex => {
+                    let position = self.pop();
+                    let object = self.pop();
+                    let span = self.span();
+                    let value = match object {
+                        Value::List(list) => {
+                            let elements = list.borrow();
+                            let i = value::list_index(&position, elements.len(), span)?;
+                            elements[i].clone()
+                        }
+                        Value::Map(map) => {
+                            let key = value::map_key(&position, span)?;
+                            let value = map.borrow().get(&key).cloned();
+                            value.ok_or_else(|| {
+                                LoxError::runtime(
+                                    ErrorCode::UndefinedKey,
+                                    span,
+                                    format!("Undefined key {}", position.repr()),
+                                )
+                            })?
+                        }
+                        _ => return Err(self.not_indexable()),
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::SetIndex => {
+                    let value = self.pop();
+                    let position = self.pop();
+                    let object = self.pop();
+                    let span = self.span();
+                    match object {
+                        Value::List(list) => {
+                            let mut elements = list.borrow_mut();
+                            let i = value::list_index(&position, elements.len(), span)?;
+                            elements[i] = value.clone();
+                        }
+                        Value::Map(map) => {
+                            let key = value::map_key(&position, span)?;
+                            map.borrow_mut().insert(key, value.clone());
+                        }
+                        _ => return Err(self.not_indexable()),
+                    }
+                    self.stack.push(value);
+                }
+            }
+        }
+    }
+
+    fn binary(&mut self, op: OpCode) -> Result<(), LoxError> {
+        let right = self.pop();
+        let left = self.pop();
+        let result = match (op, left, right) {
+            (OpCode::Equal, x, y) => Value::Bool(x == y),
+            (OpCode::NotEqual, x, y) => Value::Bool(x != y),
+            (OpCode::Subtract, Value::Number(x), Value::Number(y)) => Value::Number(x - y),
+            (OpCode::Divide, Value::Number(x), Value::Number(y)) => Value::Number(x / y),
+            (OpCode::Multiply, Value::Number(x), Value::Number(y)) => Value::Number(x * y),
+            (OpCode::Add, Value::Number(x), Value::Number(y)) => Value::Number(x + y),
+            (OpCode::Add, Value::String(x), Value::String(y)) => {
+                Value::String(value::concat(&x, &y))
+            }
+            (OpCode::Greater, Value::Number(x), Value::Number(y)) => Value::Bool(x > y),
+            (OpCode::Greater, Value::String(x), Value::String(y)) => Value::Bool(x > y),
+            (OpCode::Less, Value::Number(x), Value::Number(y)) => Value::Bool(x < y),
+            (OpCode::Less, Value::String(x), Value::String(y)) => Value::Bool(x < y),
+            (OpCode::GreaterEqual, Value::Number(x), Value::Number(y)) => Value::Bool(x >= y),
+            (OpCode::GreaterEqual, Value::String(x), Value::String(y)) => Value::Bool(x >= y),
+            (OpCode::LessEqual, Value::Number(x), Value::Number(y)) => Value::Bool(x <= y),
+            (OpCode::LessEqual, Value::String(x), Value::String(y)) => Value::Bool(x <= y),
+            _ => {
+                let message = match op {
+                    OpCode::Subtract => "Can only subtract two Numbers",
+                    OpCode::Divide => "Can only divide two Numbers",
+                    OpCode::Multiply => "Can only multiply two Numbers",
+                    OpCode::Add => "Can only add two Numbers or two Strings",
+                    OpCode::Greater => {
+                        "Can only use greater than operator on two Numbers or two Strings"
+                    }
+                    OpCode::Less => "Can only use less than operator on two Numbers or two Strings",
+                    OpCode::GreaterEqual => {
+                        "Can only use greater than or equal operator on two Numbers or two Strings"
+                    }
+                    _ => "Can only use less than or equal operator on two Numbers or two Strings",
+                };
+                return Err(self.error(ErrorCode::InvalidOperand, message));
+            }
+        };
+        self.stack.push(result);
+        Ok(())
+    }
+
+    /// Calls the value sitting below `count` arguments on the stack.
+    fn call_value(&mut self, count: usize) -> Result<(), LoxError> {
+        let base = self.stack.len() - count - 1;
+        match self.stack[base].clone() {
+            Value::Closure(closure) => self.call(closure, count),
+            Value::BoundMethod(bound) => {
+                self.stack[base] = Value::Instance(bound.receiver.clone());
+                self.call(bound.method.clone(), count)
+            }
+            Value::Class(class) => {
+                let instance = gc::alloc(RefCell::new(LoxInstance {
+                    class: class.clone(),
+                    fields: HashMap::new(),
+                }));
+                self.stack[base] = Value::Instance(instance);
+                match class.find_method(&Symbol::intern("init")) {
+                    Some(Method::Closure(initializer)) => self.call(initializer, count),
+                    _ => self.check_arity(0, count),
+                }
+            }
+            Value::Native(native) => {
+                self.check_arity(native.arity, count)?;
+                let arguments = self.stack.split_off(base + 1);
+                self.stack.pop();
+                let result = native.call(&arguments, self.span())?;
+                self.stack.push(result);
+                Ok(())
+            }
+            _ => Err(self.error(
+                ErrorCode::NotCallable,
+                "Can only call functions and classes",
+            )),
+        }
+    }
+
+    fn call(&mut self, closure: Rc<Closure>, count: usize) -> Result<(), LoxError> {
+        self.check_arity(closure.function.arity, count)?;
+        // The script's own frame doesn't count as a call
+        if self.frames.len() > MAX_CALL_DEPTH {
+            return Err(self.error(ErrorCode::StackOverflow, "Stack overflow"));
+        }
+        self.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            base: self.stack.len() - count - 1,
+        });
+        Ok(())
+    }
+
+    fn check_arity(&self, arity: usize, count: usize) -> Result<(), LoxError> {
+        if count != arity {
+            return Err(self.error(
+                ErrorCode::ArityMismatch,
+                &format!("Expected {} arguments but got {}", arity, count),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Builds a class from the method closures on top of the stack, which
+    /// sit above the superclass if there is one.
+    fn class(&mut self, function: &FunctionProto) -> Result<(), LoxError> {
+        let name = string(function, self.read_u16()).clone();
+        let has_superclass = self.read_byte() == 1;
+        let count = self.read_u16() as usize;
+        let names: Vec<u16> = (0..count).map(|_| self.read_u16()).collect();
+        let closures = self.stack.split_off(self.stack.len() - count);
+        let superclass = match has_superclass {
+            true => match self.peek(0) {
+                Value::Class(superclass) => Some(superclass.clone()),
+                _ => {
+                    return Err(
+                        self.error(ErrorCode::SuperclassNotClass, "Superclass must be a class")
+                    )
+                }
+            },
+            false => None,
+        };
+        let mut methods = HashMap::new();
+        for (name, closure) in names.into_iter().zip(closures) {
+            if let Value::Closure(closure) = closure {
+                methods.insert(string(function, name).clone(), Method::Closure(closure));
+            }
+        }
+        self.stack.push(Value::Class(gc::alloc(LoxClass {
+            name,
+            superclass,
+            methods,
+        })));
+        Ok(())
+    }
+
+    /// Returns the upvalue for a stack slot, sharing it with any closure that
+    /// already captured the same variable.
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        let existing = self
+            .open_upvalues
+            .iter()
+            .find(|upvalue| matches!(*upvalue.borrow(), Upvalue::Open(open) if open == slot));
+        if let Some(upvalue) = existing {
+            return upvalue.clone();
+        }
+        let upvalue = gc::alloc(RefCell::new(Upvalue::Open(slot)));
+        self.open_upvalues.push(upvalue.clone());
+        upvalue
+    }
+
+    /// Moves the variables in stack slots `from..` into their upvalues, as
+    /// those slots are about to be discarded.
+    fn close_upvalues(&mut self, from: usize) {
+        let stack = &self.stack;
+        self.open_upvalues.retain(|upvalue| {
+            let mut upvalue = upvalue.borrow_mut();
+            match *upvalue {
+                Upvalue::Open(slot) if slot >= from => {
+                    *upvalue = Upvalue::Closed(stack[slot].clone());
+                    false
+                }
+                _ => true,
+            }
+        });
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().expect("no active call frame")
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().expect("no active call frame")
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let frame = self.frame_mut();
+        let byte = frame.closure.function.chunk.code[frame.ip];
+        frame.ip += 1;
+        byte
+    }
+
+    fn read_u16(&mut self) -> u16 {
+        let high = self.read_byte();
+        let low = self.read_byte();
+        u16::from_be_bytes([high, low])
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("stack underflow")
+    }
+
+    fn peek(&self, distance: usize) -> &Value {
+        &self.stack[self.stack.len() - 1 - distance]
+    }
+
+    /// The source span of the instruction being executed.
+    fn span(&self) -> Span {
+        let frame = self.frame();
+        frame.closure.function.chunk.spans[frame.ip - 1]
+    }
+
+    fn error(&self, code: ErrorCode, message: &str) -> LoxError {
+        LoxError::runtime(code, self.span(), message)
+    }
+
+    fn undefined(&self, name: &str) -> LoxError {
+        self.error(
+            ErrorCode::UndefinedVariable,
+            &format!("Undefined variable {}", name),
+        )
+    }
+
+    fn not_indexable(&self) -> LoxError {
+        self.error(ErrorCode::NotIndexable, "Can only index lists and maps")
+    }
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The string constant at `index`, e.g. a variable or property name.
+fn string(function: &FunctionProto, index: u16) -> &Symbol {
+    match &function.chunk.constants[index as usize] {
+        Constant::String(name) => name,
+        _ => panic!("Name operand is not a string"),
+    }
+}
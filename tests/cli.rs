@@ -0,0 +1,65 @@
+//! Checks how the `rlox` binary reports problems with the files it's given.
+
+use std::path::PathBuf;
+use std::process::{Command, Output};
+use std::{env, fs, process};
+
+fn rlox(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// A path in the temp directory unique to this test process.
+fn temp(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("rlox-cli-{}-{}", process::id(), name))
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn missing_file_exits_with_no_input() {
+    let missing = temp("missing.lox");
+    let missing = missing.to_str().unwrap();
+    for args in [vec![missing], vec!["compile", missing]] {
+        let output = rlox(&args);
+        assert_eq!(output.status.code(), Some(66), "{:?}", args);
+        assert!(
+            stderr(&output).starts_with("error: Can't read "),
+            "{:?}",
+            args
+        );
+    }
+}
+
+#[test]
+fn invalid_utf8_exits_with_data_error() {
+    let path = temp("latin1.lox");
+    fs::write(&path, b"print \"caf\xe9\";").unwrap();
+    let path_str = path.to_str().unwrap();
+    for args in [vec![path_str], vec!["compile", path_str]] {
+        let output = rlox(&args);
+        assert_eq!(output.status.code(), Some(65), "{:?}", args);
+        assert!(stderr(&output).contains("isn't valid UTF-8"), "{:?}", args);
+    }
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn failed_write_exits_with_io_error() {
+    let input = temp("write.lox");
+    fs::write(&input, "print 1;").unwrap();
+    let output_path = temp("no-such-directory").join("write.loxc");
+    let output = rlox(&[
+        "compile",
+        input.to_str().unwrap(),
+        "-o",
+        output_path.to_str().unwrap(),
+    ]);
+    assert_eq!(output.status.code(), Some(74));
+    assert!(stderr(&output).starts_with("error: Can't write to "));
+    fs::remove_file(input).unwrap();
+}
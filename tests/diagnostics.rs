@@ -0,0 +1,192 @@
+//! Golden tests of how the `rlox` binary renders errors from each phase, and
+//! of when it colors them.
+
+use rlox::ColorChoice;
+use std::ffi::OsStr;
+use std::path::PathBuf;
+use std::process::Command;
+use std::{env, fs, process};
+
+/// Runs `rlox` on a file called `name` holding `contents`, from the file's
+/// directory so the path in the output is just `name`. Returns the exit code
+/// and what was printed to stderr.
+fn rlox(name: &str, contents: &[u8], args: &[&str]) -> (i32, String) {
+    let directory: PathBuf =
+        env::temp_dir().join(format!("rlox-diagnostics-{}-{}", process::id(), name));
+    fs::create_dir_all(&directory).unwrap();
+    fs::write(directory.join(name), contents).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .current_dir(&directory)
+        .args(args)
+        .arg(name)
+        .output()
+        .unwrap();
+    fs::remove_dir_all(directory).unwrap();
+    (
+        output.status.code().unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+/// Checks both backends render the error in `source` as `expected`.
+fn renders(name: &str, source: &str, code: i32, expected: &str) {
+    for args in [&["--color", "never"][..], &["--color", "never", "--vm"]] {
+        assert_eq!(
+            rlox(name, source.as_bytes(), args),
+            (code, expected.to_string()),
+            "{:?}",
+            args
+        );
+    }
+}
+
+#[test]
+fn scan_error() {
+    renders(
+        "scan.lox",
+        "var s = \"unterminated;\n",
+        65,
+        "\
+error[E0102]: Unterminated string
+ --> scan.lox:1:9
+  |
+1 | var s = \"unterminated;
+  |         ^^^^^^^^^^^^^^
+  = help: add a closing '\"' to end the string
+
+",
+    );
+}
+
+#[test]
+fn parse_error() {
+    renders(
+        "parse.lox",
+        "print (1 + 2;\n",
+        65,
+        "\
+error[E0201]: Grouping did not end in right paren
+ --> parse.lox:1:13
+  |
+1 | print (1 + 2;
+  |             ^
+
+",
+    );
+}
+
+#[test]
+fn resolve_error() {
+    renders(
+        "resolve.lox",
+        "{\n  var a = a;\n}\n",
+        65,
+        "\
+error[E0301]: Can't read local variable in its own initializer at 'a'
+ --> resolve.lox:2:11
+  |
+2 |   var a = a;
+  |           ^
+  = help: declare the variable first, then assign to it
+
+",
+    );
+}
+
+#[test]
+fn runtime_error() {
+    renders(
+        "runtime.lox",
+        "var x = \"a\";\nprint x - 1;\n",
+        70,
+        "\
+error[E0401]: Can only subtract two Numbers
+ --> runtime.lox:2:9
+  |
+2 | print x - 1;
+  |         ^
+
+",
+    );
+}
+
+#[test]
+fn compile_error() {
+    let locals: String = (0..256)
+        .map(|i| format!("  var l{} = {};\n", i, i))
+        .collect();
+    let source = format!("fun f() {{\n{}}}\n", locals);
+    // The gutter widens to fit the line number
+    let expected = "\
+error[E0502]: Too many local variables in function
+   --> limits.lox:257:7
+    |
+257 |   var l255 = 255;
+    |       ^^^^
+
+";
+    assert_eq!(
+        rlox(
+            "limits.lox",
+            source.as_bytes(),
+            &["--color", "never", "--vm"]
+        ),
+        (65, expected.to_string())
+    );
+}
+
+#[test]
+fn bytecode_error_has_no_snippet() {
+    let mut bytes = rlox::loxc::MAGIC.to_vec();
+    bytes.extend((rlox::loxc::VERSION + 1).to_le_bytes());
+    let expected = format!(
+        "\
+error[E0505]: Invalid bytecode: Unsupported .loxc version {} (expected {})
+ --> bad.loxc
+  = help: recompile the script with 'rlox compile'
+
+",
+        rlox::loxc::VERSION + 1,
+        rlox::loxc::VERSION
+    );
+    assert_eq!(
+        rlox("bad.loxc", &bytes, &["--color", "never"]),
+        (65, expected)
+    );
+}
+
+#[test]
+fn colored_error() {
+    let expected = "\
+\x1b[1;31merror[E0401]\x1b[0m\x1b[1m: Can only subtract two Numbers\x1b[0m
+ \x1b[1;34m-->\x1b[0m colored.lox:1:11
+  \x1b[1;34m|\x1b[0m
+\x1b[1;34m1\x1b[0m \x1b[1;34m|\x1b[0m print \"a\" - 1;
+  \x1b[1;34m|\x1b[0m           \x1b[1;31m^\x1b[0m
+
+";
+    let source = b"print \"a\" - 1;\n";
+    assert_eq!(
+        rlox("colored.lox", source, &["--color", "always"]),
+        (70, expected.to_string())
+    );
+    // Captured stderr isn't a terminal, so `auto` leaves color out
+    let (_, auto) = rlox("colored.lox", source, &["--color", "auto"]);
+    assert!(!auto.contains('\x1b'), "{:?}", auto);
+}
+
+#[test]
+fn color_choice() {
+    let set = Some(OsStr::new("1"));
+    let empty = Some(OsStr::new(""));
+    for (no_color, terminal) in [(None, true), (None, false), (set, true), (empty, false)] {
+        assert!(ColorChoice::Always.enabled_for(no_color, terminal));
+        assert!(!ColorChoice::Never.enabled_for(no_color, terminal));
+    }
+    assert!(ColorChoice::Auto.enabled_for(None, true));
+    assert!(!ColorChoice::Auto.enabled_for(None, false));
+    // NO_COLOR counts only when it isn't empty
+    assert!(!ColorChoice::Auto.enabled_for(set, true));
+    assert!(ColorChoice::Auto.enabled_for(empty, true));
+    assert!(!ColorChoice::Auto.enabled_for(set, false));
+}
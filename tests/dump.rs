@@ -0,0 +1,129 @@
+//! Golden test of what `--dump-bytecode` prints.
+
+use std::process::Command;
+use std::{env, fs, process};
+
+/// Covers forward jumps from `if`, `else` and `and`, a loop, closures that
+/// capture locals and upvalues, and a captured local closed at block end.
+const SCRIPT: &str = "\
+fun counter() {
+  var count = 0;
+  fun increment() {
+    count = count + 1;
+    fun peek() { return count; }
+    return peek;
+  }
+  return increment;
+}
+var next = counter();
+for (var i = 0; i < 2; i = i + 1) {
+  if (i == 1) print next()(); else print i;
+}
+{
+  var shown = true and false;
+  fun show() { print shown; }
+  show();
+}
+";
+
+const EXPECTED: &str = "\
+== <script> ==
+0000    1 Closure             0 <fn counter>
+0003    | DefineGlobal        1 \"counter\"
+0006   10 GetGlobal           2 \"counter\"
+0009    | Call                0
+0011    | DefineGlobal        3 \"next\"
+0014   11 Constant            4 0
+0017    | GetLocal            1
+0019    | Constant            5 2
+0022    | Less
+0023    | JumpIfFalse        23 -> 0064
+0026    | Pop
+0027   12 GetLocal            1
+0029    | Constant            6 1
+0032    | Equal
+0033    | JumpIfFalse        33 -> 0048
+0036    | Pop
+0037    | GetGlobal           7 \"next\"
+0040    | Call                0
+0042    | Call                0
+0044    | Print
+0045    | Jump               45 -> 0052
+0048    | Pop
+0049    | GetLocal            1
+0051    | Print
+0052   11 GetLocal            1
+0054    | Constant            8 1
+0057    | Add
+0058    | SetLocal            1
+0060    | Pop
+0061    | Loop               61 -> 0017
+0064    | Pop
+0065    | Pop
+0066   15 True
+0067    | JumpIfFalse        67 -> 0072
+0070    | Pop
+0071    | False
+0072   16 Closure             9 <fn show>
+0075    |   local 1
+0077   17 GetLocal            2
+0079    | Call                0
+0081    | Pop
+0082   14 Pop
+0083    | CloseUpvalue
+0084    | Nil
+0085    | Return
+
+== <fn counter> ==
+0000    2 Constant            0 0
+0003    3 Closure             1 <fn increment>
+0006    |   local 1
+0008    8 GetLocal            2
+0010    | Return
+0011    1 Nil
+0012    | Return
+
+== <fn increment> ==
+0000    4 GetUpvalue          0
+0002    | Constant            0 1
+0005    | Add
+0006    | SetUpvalue          0
+0008    | Pop
+0009    5 Closure             1 <fn peek>
+0012    |   upvalue 0
+0014    6 GetLocal            1
+0016    | Return
+0017    3 Nil
+0018    | Return
+
+== <fn peek> ==
+0000    5 GetUpvalue          0
+0002    | Return
+0003    | Nil
+0004    | Return
+
+== <fn show> ==
+0000   16 GetUpvalue          0
+0002    | Print
+0003    | Nil
+0004    | Return
+";
+
+#[test]
+fn dumps_bytecode_without_running_it() {
+    let path = env::temp_dir().join(format!("rlox-dump-{}.lox", process::id()));
+    fs::write(&path, SCRIPT).unwrap();
+    for args in [&["--dump-bytecode"][..], &["--dump-bytecode", "--vm"]] {
+        let output = Command::new(env!("CARGO_BIN_EXE_rlox"))
+            .args(args)
+            .arg(&path)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", args);
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), EXPECTED);
+    }
+    fs::remove_file(path).unwrap();
+    // The library prints the same
+    let script = rlox::compile(SCRIPT).unwrap();
+    assert_eq!(rlox::disassemble(&script), EXPECTED);
+}
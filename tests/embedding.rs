@@ -0,0 +1,25 @@
+//! Checks the knobs `Lox` gives embedders on both backends.
+
+use rlox::{Backend, ErrorCode, Lox};
+
+#[test]
+fn step_limit_stops_runaway_loops() {
+    for backend in [Backend::TreeWalk, Backend::Vm] {
+        let mut lox = Lox::with_backend(backend);
+        lox.interpreter.set_step_limit(Some(1000));
+        lox.vm.set_step_limit(Some(1000));
+        let errors = lox.eval("var i = 0; while (true) i = i + 1;").unwrap_err();
+        assert_eq!(errors.len(), 1, "{:?}", backend);
+        assert_eq!(errors[0].code(), ErrorCode::StepLimitExceeded);
+
+        // The budget is per call, and loops that end within it are fine
+        let value = lox
+            .eval("for (var j = 0; j < 10; j = j + 1) {} i;")
+            .unwrap();
+        assert!(
+            matches!(value, rlox::Value::Number(n) if n > 0.0),
+            "{:?}",
+            backend
+        );
+    }
+}
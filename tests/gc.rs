@@ -0,0 +1,75 @@
+//! Checks that the collector frees objects kept alive only by reference
+//! cycles, on both backends.
+
+use rlox::{gc, Backend, Lox, Value};
+use std::rc::{Rc, Weak};
+
+/// Whether the object `value` refers to is still alive, checked without
+/// keeping it alive.
+fn watch(value: Value) -> Box<dyn Fn() -> bool> {
+    fn alive<T: ?Sized + 'static>(rc: &Rc<T>) -> Box<dyn Fn() -> bool> {
+        let weak: Weak<T> = Rc::downgrade(rc);
+        Box::new(move || weak.upgrade().is_some())
+    }
+    match &value {
+        Value::List(list) => alive(list),
+        Value::Map(map) => alive(map),
+        Value::Instance(instance) => alive(instance),
+        Value::Function(function) => alive(function),
+        Value::Closure(closure) => alive(closure),
+        other => panic!("{:?} isn't a heap object", other),
+    }
+}
+
+/// Runs `setup`, which must leave a cycle in the global `cycle`, then drops
+/// the global and checks that collecting frees what it pointed to.
+fn collects(setup: &str) {
+    for backend in [Backend::TreeWalk, Backend::Vm] {
+        let mut lox = Lox::with_backend(backend);
+        lox.eval(setup).unwrap();
+        let alive = watch(lox.eval("cycle;").unwrap());
+
+        lox.eval("cycle = nil;").unwrap();
+        assert!(alive(), "{:?}: freed without collecting", backend);
+        assert!(gc::collect() > 0, "{:?}: {}", backend, setup);
+        assert!(!alive(), "{:?}: {}", backend, setup);
+    }
+}
+
+#[test]
+fn frees_a_list_that_contains_itself() {
+    collects("var cycle = [1, 2]; cycle[0] = cycle;");
+}
+
+#[test]
+fn frees_a_map_that_contains_itself() {
+    collects("var cycle = {\"a\": nil}; cycle[\"self\"] = cycle;");
+}
+
+#[test]
+fn frees_an_instance_and_a_closure_that_refer_to_each_other() {
+    collects(
+        "
+        class Box {}
+        var cycle = Box();
+        {
+          var box = cycle;
+          fun get() { return box; }
+          box.get = get;
+        }
+        ",
+    );
+}
+
+#[test]
+fn keeps_cycles_that_are_still_reachable() {
+    for backend in [Backend::TreeWalk, Backend::Vm] {
+        let mut lox = Lox::with_backend(backend);
+        lox.eval("var cycle = [nil]; cycle[0] = cycle;").unwrap();
+        let alive = watch(lox.eval("cycle;").unwrap());
+        gc::collect();
+        assert!(alive(), "{:?}", backend);
+        let value = lox.eval("cycle[0][0] == cycle;").unwrap();
+        assert!(matches!(value, Value::Bool(true)), "{:?}", backend);
+    }
+}
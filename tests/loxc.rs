@@ -0,0 +1,157 @@
+//! Round-trips scripts through the `.loxc` format and checks that `decode`
+//! rejects corrupt files instead of handing them to the VM.
+
+use rlox::chunk::{Constant, FunctionProto, OpCode};
+use rlox::output::SharedBuffer;
+use rlox::{loxc, Backend, Lox, LoxError, Span, Vm};
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::thread;
+
+/// What running a script printed, and the error it stopped with if any.
+fn run(script: Rc<FunctionProto>) -> (String, Option<LoxError>) {
+    let mut vm = Vm::new();
+    let output = SharedBuffer::new();
+    vm.set_output(Box::new(output.clone()));
+    let error = vm.interpret(script).err();
+    (output.contents(), error)
+}
+
+fn eval(source: &str) -> (String, Option<LoxError>) {
+    let mut lox = Lox::with_backend(Backend::Vm);
+    let output = SharedBuffer::new();
+    lox.vm.set_output(Box::new(output.clone()));
+    let error = lox.eval(source).err().map(|mut errors| errors.remove(0));
+    (output.contents(), error)
+}
+
+#[test]
+fn decoded_scripts_run_like_their_source() {
+    // Some scripts nest as deeply as the parser allows, which outgrows a test
+    // thread's stack in debug builds
+    thread::Builder::new()
+        .stack_size(8 << 20)
+        .spawn(round_trip_scripts)
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+fn round_trip_scripts() {
+    let directory = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/lox");
+    let mut compiled = 0;
+    for entry in fs::read_dir(directory).unwrap() {
+        let path = entry.unwrap().path();
+        let source = fs::read_to_string(&path).unwrap();
+        // Scripts that fail to compile have nothing to encode
+        let Ok(script) = rlox::compile(&source) else {
+            continue;
+        };
+        let bytes = loxc::encode(&script);
+        assert!(loxc::is_loxc(&bytes));
+        let decoded = loxc::decode(&bytes)
+            .unwrap_or_else(|error| panic!("{}: {}", path.display(), error.message()));
+        assert_eq!(
+            rlox::disassemble(&decoded),
+            rlox::disassemble(&script),
+            "{}",
+            path.display()
+        );
+        assert_eq!(run(decoded), eval(&source), "{}", path.display());
+        compiled += 1;
+    }
+    assert!(compiled > 0, "no scripts in tests/lox compiled");
+}
+
+fn decode_error(bytes: &[u8]) -> String {
+    match loxc::decode(bytes) {
+        Ok(_) => panic!("decoded corrupt bytes {:?}", bytes),
+        Err(error) => error.message().to_string(),
+    }
+}
+
+fn sample() -> Vec<u8> {
+    let script = rlox::compile("fun add(a, b) { return a + b; } print add(1, 2);").unwrap();
+    loxc::encode(&script)
+}
+
+#[test]
+fn rejects_a_bad_magic() {
+    let mut bytes = sample();
+    bytes[0] = b'X';
+    assert_eq!(decode_error(&bytes), "Invalid bytecode: Not a .loxc file");
+}
+
+#[test]
+fn rejects_an_unknown_version() {
+    let mut bytes = sample();
+    let version = (loxc::VERSION + 1).to_le_bytes();
+    bytes[4..6].copy_from_slice(&version);
+    assert_eq!(
+        decode_error(&bytes),
+        format!(
+            "Invalid bytecode: Unsupported .loxc version {} (expected {})",
+            loxc::VERSION + 1,
+            loxc::VERSION
+        )
+    );
+}
+
+#[test]
+fn rejects_truncated_input() {
+    let bytes = sample();
+    for length in 0..bytes.len() {
+        decode_error(&bytes[..length]);
+    }
+    assert_eq!(
+        decode_error(&bytes[..bytes.len() - 1]),
+        "Invalid bytecode: Unexpected end of file"
+    );
+}
+
+/// A script made of `code`, with one number constant.
+fn script(code: &[u8]) -> Vec<u8> {
+    let mut script = FunctionProto::default();
+    for byte in code {
+        script.chunk.write(*byte, Span::default());
+    }
+    script.chunk.constants.push(Constant::Number(1.0));
+    loxc::encode(&script)
+}
+
+#[test]
+fn rejects_an_out_of_range_constant() {
+    let bytes = script(&[OpCode::Constant as u8, 0, 1, OpCode::Return as u8]);
+    assert_eq!(
+        decode_error(&bytes),
+        "Invalid bytecode: Constant index out of range at offset 1 in <script>"
+    );
+    // Index 0 is the one constant there is
+    loxc::decode(&script(&[
+        OpCode::Constant as u8,
+        0,
+        0,
+        OpCode::Return as u8,
+    ]))
+    .unwrap();
+}
+
+#[test]
+fn rejects_an_out_of_range_local() {
+    // Only slot 0, the script itself, is on the stack
+    let bytes = script(&[OpCode::GetLocal as u8, 1, OpCode::Return as u8]);
+    assert_eq!(
+        decode_error(&bytes),
+        "Invalid bytecode: Local slot out of range at offset 0 in <script>"
+    );
+}
+
+#[test]
+fn rejects_a_stack_underflow() {
+    let bytes = script(&[OpCode::Nil as u8, OpCode::Add as u8, OpCode::Return as u8]);
+    assert_eq!(
+        decode_error(&bytes),
+        "Invalid bytecode: Stack underflow at offset 1 in <script>"
+    );
+}
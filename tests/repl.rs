@@ -0,0 +1,103 @@
+//! Checks what the REPL echoes on both backends, and how values are quoted
+//! when it does.
+
+use rlox::value::Quoted;
+use rlox::Lox;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Feeds `input` to the REPL and returns what it printed to stdout.
+fn repl(input: &str, args: &[&str]) -> String {
+    repl_output(input, args).0
+}
+
+/// Feeds `input` to the REPL and returns what it printed to stdout and
+/// stderr.
+fn repl_output(input: &str, args: &[&str]) -> (String, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    (
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+#[test]
+fn echoes_expression_statements() {
+    let input = "\
+fun f() {}
+var a = \"back\\slash\";
+nil;
+f();
+a;
+1 + 2;
+\"tab\there\";
+print a;
+if (true) 1;
+";
+    let expected = "\
+nil
+nil
+\"back\\\\slash\"
+3
+\"tab\\there\"
+back\\slash
+";
+    assert_eq!(repl(input, &[]), expected);
+    assert_eq!(repl(input, &["--vm"]), expected);
+}
+
+#[test]
+fn errors_show_the_line_they_come_from() {
+    let input = "\
+fun half(x) { return x / \"2\"; }
+var y = 1;
+half(y);
+";
+    let expected = "\
+error[E0401]: Can only divide two Numbers
+ --> <stdin>:1:24
+  |
+1 | fun half(x) { return x / \"2\"; }
+  |                        ^
+
+";
+    for args in [&["--color", "never"][..], &["--color", "never", "--vm"]] {
+        assert_eq!(repl_output(input, args), (String::new(), expected.into()));
+    }
+}
+
+#[test]
+fn quotes_strings_unambiguously() {
+    let cases = [
+        ("plain", r#""plain""#),
+        ("say \"hi\"", r#""say \"hi\"""#),
+        ("back\\slash", r#""back\\slash""#),
+        ("two\nlines\r\n", r#""two\nlines\r\n""#),
+        ("tab\there", r#""tab\there""#),
+        ("bell\u{7}", r#""bell\u{7}""#),
+        ("ünïcode", r#""ünïcode""#),
+    ];
+    for (string, quoted) in cases {
+        assert_eq!(Quoted(string).to_string(), quoted);
+    }
+}
+
+#[test]
+fn repr_of_a_multiline_string() {
+    let mut lox = Lox::new();
+    let value = lox.eval("\"one\ntwo\";").unwrap();
+    assert_eq!(value.repr().to_string(), r#""one\ntwo""#);
+}
@@ -0,0 +1,181 @@
+//! Runs every script in `tests/lox` on both backends and checks they behave
+//! the same. Scripts state what they should do in comments: one
+//! `// expect: <text>` per line printed, in order, and one
+//! `// expect error: <code> <line>:<column> <message>` per error `eval`
+//! returns.
+
+use rlox::output::SharedBuffer;
+use rlox::{Backend, Lox, LoxError};
+use std::{fs, path::PathBuf, thread};
+
+/// Printed output and errors, with each error described as in the scripts.
+type Outcome = (String, Vec<String>);
+
+fn expected(source: &str) -> Outcome {
+    let mut output = String::new();
+    let mut errors = vec![];
+    for line in source.lines() {
+        if let Some((_, text)) = line.split_once("// expect: ") {
+            output.push_str(text);
+            output.push('\n');
+        } else if let Some((_, error)) = line.split_once("// expect error: ") {
+            errors.push(error.to_string());
+        }
+    }
+    (output, errors)
+}
+
+fn describe(error: &LoxError) -> String {
+    let span = error.span();
+    format!(
+        "{} {}:{} {}",
+        error.code(),
+        span.line,
+        span.column,
+        error.message()
+    )
+}
+
+/// Runs `source` on a thread with a main thread's stack: test threads get
+/// less, which the tree-walker outgrows before its own call depth limit in
+/// debug builds. With `stress`, the collector runs on every allocation.
+fn run(source: &str, backend: Backend, stress: bool) -> Outcome {
+    let source = source.to_string();
+    thread::Builder::new()
+        .stack_size(8 << 20)
+        .spawn(move || {
+            // The heap is per thread, so this only affects this script
+            rlox::gc::set_stress(stress);
+            eval(&source, backend)
+        })
+        .unwrap()
+        .join()
+        .unwrap()
+}
+
+fn eval(source: &str, backend: Backend) -> Outcome {
+    let mut lox = Lox::with_backend(backend);
+    let output = SharedBuffer::new();
+    lox.interpreter.set_output(Box::new(output.clone()));
+    lox.vm.set_output(Box::new(output.clone()));
+    let errors = match lox.eval(source) {
+        Ok(_) => vec![],
+        Err(errors) => errors.iter().map(describe).collect(),
+    };
+    (output.contents(), errors)
+}
+
+#[test]
+fn scripts() {
+    check_scripts(false);
+}
+
+/// Scripts too big to collect on every allocation: each collection walks the
+/// whole heap, and these build chains of thousands of objects.
+const TOO_BIG_FOR_STRESS: &[&str] = &["teardown.lox"];
+
+/// Collecting as often as possible shakes out objects the collector frees
+/// while something still uses them.
+#[test]
+fn scripts_under_gc_stress() {
+    check_scripts(true);
+}
+
+fn check_scripts(stress: bool) {
+    let directory = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/lox");
+    let mut paths: Vec<PathBuf> = fs::read_dir(directory)
+        .expect("tests/lox is missing")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "lox"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "no scripts in tests/lox");
+
+    let mut failures = vec![];
+    for path in &paths {
+        let name = path.file_name().unwrap().to_str().unwrap();
+        if stress && TOO_BIG_FOR_STRESS.contains(&name) {
+            continue;
+        }
+        let source = fs::read_to_string(path).unwrap();
+        let expected = expected(&source);
+        for backend in [Backend::TreeWalk, Backend::Vm] {
+            let actual = run(&source, backend, stress);
+            if actual != expected {
+                failures.push(format!(
+                    "{} on {:?}{}\n  expected: {:?}\n    actual: {:?}",
+                    path.display(),
+                    backend,
+                    if stress { " under GC stress" } else { "" },
+                    expected,
+                    actual
+                ));
+            }
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+/// Declarations of `count` variables named `prefix0`, `prefix1`, ….
+fn vars(prefix: &str, count: usize) -> String {
+    (0..count)
+        .map(|i| format!("var {}{} = {};\n", prefix, i, i))
+        .collect()
+}
+
+/// The VM's instruction format bounds how big a function can be, so these
+/// scripts fail to compile for it but run on the tree-walker. The README lists
+/// the same limits.
+#[test]
+fn vm_limits() {
+    // Locals, which need no constants, keep the jump cases to one limit
+    let statements = "x = x + x;\n".repeat(9000);
+    let upvalues = (0..200)
+        .map(|i| format!("a{} + b{}", i, i))
+        .collect::<Vec<_>>()
+        .join(" + ");
+    let cases = [
+        (
+            format!("fun f() {{\n{}}}", vars("l", 256)),
+            "E0502 Too many local variables in function",
+        ),
+        (
+            format!(
+                "fun outer() {{\n{}fun middle() {{\n{}fun inner() {{ return {}; }}\n}}\n}}",
+                vars("a", 200),
+                vars("b", 200),
+                upvalues
+            ),
+            "E0503 Too many closure variables in function",
+        ),
+        (
+            (0..=65536).map(|i| format!("{};\n", i)).collect(),
+            "E0501 Too many constants in one chunk",
+        ),
+        (
+            format!("{{ var x = 0; if (x == 0) {{\n{}}} }}", statements),
+            "E0504 Too much code to jump over",
+        ),
+        (
+            format!("{{ var x = 0; while (x != 0) {{\n{}}} }}", statements),
+            "E0504 Loop body too large",
+        ),
+    ];
+    for (source, error) in cases {
+        assert_eq!(
+            run(&source, Backend::TreeWalk, false),
+            (String::new(), vec![])
+        );
+        let (_, errors) = run(&source, Backend::Vm, false);
+        // Drop the position, which for generated code says little
+        let errors: Vec<String> = errors
+            .iter()
+            .map(|error| {
+                let (code, rest) = error.split_once(' ').unwrap();
+                let (_, message) = rest.split_once(' ').unwrap();
+                format!("{} {}", code, message)
+            })
+            .collect();
+        assert_eq!(errors, [error]);
+    }
+}
@@ -0,0 +1,29 @@
+//! Checks that the interner lets go of strings no symbol uses any more.
+
+use rlox::symbol::{self, Symbol};
+use rlox::Lox;
+use std::rc::Rc;
+
+#[test]
+fn prunes_strings_no_symbol_uses() {
+    let unused = Rc::downgrade(&Symbol::intern("only-used-here").to_rc());
+    let kept = Symbol::intern("still-used");
+    assert!(symbol::prune() > 0);
+    assert!(unused.upgrade().is_none());
+
+    // Symbols that outlive a prune still compare equal to new ones
+    assert_eq!(Symbol::intern("still-used"), kept);
+}
+
+#[test]
+fn evaluating_new_names_forever_stays_bounded() {
+    let mut lox = Lox::new();
+    let first = Rc::downgrade(&Symbol::intern("name0").to_rc());
+    for i in 0..5000 {
+        lox.eval(&format!("{{ var name{} = {}; }}", i, i)).unwrap();
+    }
+    // Interning prunes as it goes, without being asked to
+    assert!(first.upgrade().is_none());
+    // What's left to prune is what was interned since the last prune
+    assert!(symbol::prune() < 1024);
+}